@@ -0,0 +1,161 @@
+//! Benchmarks of the hot paths of a simulation, run with `cargo bench`.
+//!
+//! Every input is built from a fixed seed, so a change in the numbers comes from
+//! a change in the code and not from a different run.
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use std::sync::Arc;
+use tower_sim::bank::{Bank, BankKey, Block, ID};
+use tower_sim::config::SimConfig;
+use tower_sim::forks::Forks;
+use tower_sim::network::Network;
+use tower_sim::node::Node;
+use tower_sim::slashing::Detector;
+use tower_sim::tower::{Slot, Tower, Vote, DEPTH};
+
+const SEED: u64 = 7;
+//nodes in the benchmarks of a single bank, fork or node
+const NODES: usize = 1000;
+//slots of the fork tree they run on, enough for the roots to advance
+const SLOTS: Slot = 64;
+
+fn config(num_nodes: usize) -> Arc<SimConfig> {
+    let mut config = SimConfig::new(num_nodes);
+    config.seed = SEED;
+    Arc::new(config)
+}
+
+//every node votes on the parent of each block, which lands in the block
+fn votes(towers: &mut [Tower], parent: Slot) -> Vec<(ID, Vec<Vote>)> {
+    towers
+        .iter_mut()
+        .enumerate()
+        .map(|(id, t)| {
+            let _ = t.apply(&Vote::new(parent));
+            let mut votes = t.votes();
+            for v in &mut votes {
+                v.lockout = 2;
+            }
+            (id, votes)
+        })
+        .collect()
+}
+
+//a chain of blocks where every fourth slot is a block on the grandparent that
+//nobody votes on, returns the forks, the towers and the tip of the chain
+fn fork_tree(config: &Arc<SimConfig>, slots: Slot) -> (Forks, Vec<Tower>, BankKey) {
+    let mut forks = Forks::new(config.clone());
+    let mut towers = vec![Tower::new(config.depth); config.num_nodes()];
+    let mut parent = (0, 0);
+    for slot in 1..=slots {
+        let grandparent = forks.fork_map[&parent].parent_key();
+        let minority = slot % 4 == 0 && forks.fork_map.contains_key(&grandparent);
+        let on = if minority { grandparent } else { parent };
+        let block = Block {
+            slot,
+            parent: on.0,
+            parent_id: on.1,
+            producer: 0,
+            votes: if minority {
+                vec![]
+            } else {
+                votes(&mut towers, parent.0)
+            },
+        };
+        forks.apply(&block).unwrap();
+        if !minority {
+            parent = (slot, block.id());
+        }
+    }
+    (forks, towers, parent)
+}
+
+fn tower_apply(c: &mut Criterion) {
+    let mut tower = Tower::new(DEPTH);
+    for slot in 1..=40 {
+        tower.apply(&Vote::new(slot)).unwrap();
+    }
+    c.bench_function("tower_apply", |b| {
+        b.iter_batched(
+            || tower.clone(),
+            |mut t| t.apply(&Vote::new(41)),
+            BatchSize::SmallInput,
+        )
+    });
+}
+
+fn bank_child_apply(c: &mut Criterion) {
+    let config = config(NODES);
+    let (forks, mut towers, tip) = fork_tree(&config, SLOTS);
+    let block = Block {
+        slot: tip.0 + 1,
+        parent: tip.0,
+        parent_id: tip.1,
+        producer: 0,
+        votes: votes(&mut towers, tip.0),
+    };
+    let parent = &forks.fork_map[&tip];
+    c.bench_function("bank_child_apply", |b| {
+        b.iter_batched(
+            || Detector::new(&config),
+            |mut detector| {
+                let mut bank: Bank = parent.child(block.slot);
+                bank.apply(&block, &forks.ancestry, &mut detector).unwrap();
+                bank
+            },
+            BatchSize::LargeInput,
+        )
+    });
+}
+
+fn build_fork_weights(c: &mut Criterion) {
+    let (mut forks, _, _) = fork_tree(&config(NODES), SLOTS);
+    c.bench_function("build_fork_weights", |b| {
+        b.iter(|| forks.build_fork_weights())
+    });
+}
+
+fn node_vote(c: &mut Criterion) {
+    let config = config(NODES);
+    let (forks, _, tip) = fork_tree(&config, SLOTS);
+    //a primary node that sees every block
+    let id = *forks.fork_map[&tip].subcom.primary.iter().min().unwrap();
+    c.bench_function("node_vote", |b| {
+        b.iter_batched(
+            || {
+                let mut node = Node::zero(id, &config);
+                for key in forks.fork_map.keys() {
+                    node.set_active_block(*key);
+                }
+                node
+            },
+            |mut node| node.vote(&forks).unwrap(),
+            BatchSize::SmallInput,
+        )
+    });
+}
+
+fn network_step(c: &mut Criterion) {
+    let mut group = c.benchmark_group("network_step");
+    group.sample_size(10);
+    for num_nodes in [1000, 5000, 10000] {
+        let mut network = Network::new(SimConfig::clone(&config(num_nodes)));
+        //past the first roots, so the step runs on a steady fork tree
+        for _ in 0..32 {
+            network.step(1).unwrap();
+        }
+        group.bench_function(BenchmarkId::from_parameter(num_nodes), |b| {
+            b.iter(|| network.step(1).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    tower_apply,
+    bank_child_apply,
+    build_fork_weights,
+    node_vote,
+    network_step
+);
+criterion_main!(benches);
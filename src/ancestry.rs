@@ -0,0 +1,220 @@
+//! Ancestry index of the bank tree.
+//!
+//! Every bank keeps skip pointers to its ancestors 1, 2, 4, ... levels up, so
+//! `is_ancestor`, `version` and `lca` take O(log depth) steps instead of walking
+//! the parents of a fork and collecting them into a set.
+use crate::bank::BankKey;
+use crate::tower::Slot;
+use std::collections::HashMap;
+
+#[derive(Clone, Debug)]
+struct Entry {
+    //levels below the first bank that was indexed
+    depth: u64,
+    //the ancestors 2^k levels up, pointers to pruned banks may be missing
+    jumps: Vec<BankKey>,
+}
+
+/// The banks that descend from the lowest root. The parent of the lowest root
+/// is still part of every fork, same as in `Forks::compute_fork`.
+#[derive(Clone, Debug)]
+pub struct Ancestry {
+    entries: HashMap<BankKey, Entry>,
+    //parent of the lowest root
+    base: BankKey,
+}
+
+impl Ancestry {
+    pub fn new(root: BankKey, parent: BankKey) -> Self {
+        let mut entries = HashMap::new();
+        entries.insert(
+            root,
+            Entry {
+                depth: 0,
+                jumps: vec![],
+            },
+        );
+        Ancestry {
+            entries,
+            base: parent,
+        }
+    }
+
+    pub fn insert(&mut self, key: BankKey, parent: BankKey) {
+        let depth = self.entries[&parent].depth + 1;
+        let mut jumps = vec![parent];
+        while let Some(next) = self
+            .entries
+            .get(jumps.last().unwrap())
+            .and_then(|e| e.jumps.get(jumps.len() - 1))
+        {
+            jumps.push(*next);
+        }
+        self.entries.insert(key, Entry { depth, jumps });
+    }
+
+    /// keep the banks that descend from the new lowest root, `base` is its parent
+    pub fn retain(&mut self, keep: impl Fn(&BankKey) -> bool, base: BankKey) {
+        self.entries.retain(|k, _| keep(k));
+        self.base = base;
+    }
+
+    pub fn contains(&self, key: BankKey) -> bool {
+        self.entries.contains_key(&key)
+    }
+
+    /// the parent of the lowest root, the oldest bank of every fork
+    pub fn base(&self) -> BankKey {
+        self.base
+    }
+
+    /// the slots from `key` down to `base` and the version of each, only for reporting a fork
+    pub fn fork(&self, key: BankKey) -> HashMap<Slot, u64> {
+        let mut fork = HashMap::from([self.base]);
+        let mut next = Some(key);
+        while let Some(k) = next.filter(|k| self.contains(*k)) {
+            fork.insert(k.0, k.1);
+            next = self.entries[&k].jumps.first().cloned();
+        }
+        fork
+    }
+
+    /// the version of `slot` in the fork of `key`, `None` if the fork doesn't have the slot
+    pub fn version(&self, mut key: BankKey, slot: Slot) -> Option<u64> {
+        loop {
+            if key.0 <= slot {
+                return (key.0 == slot).then_some(key.1);
+            }
+            let e = self.entries.get(&key)?;
+            //the furthest ancestor that isn't older than `slot`, slots only go down a fork
+            key = e
+                .jumps
+                .iter()
+                .rev()
+                .find(|j| j.0 >= slot && (self.contains(**j) || **j == self.base))
+                .cloned()
+                .or_else(|| e.jumps.is_empty().then_some(self.base))?;
+        }
+    }
+
+    /// whether `a` is in the fork of `b`, a bank is its own ancestor
+    pub fn is_ancestor(&self, a: BankKey, b: BankKey) -> bool {
+        if a == b {
+            return true;
+        }
+        let b_depth = match self.entries.get(&b) {
+            Some(e) => e.depth,
+            None => return false,
+        };
+        match self.entries.get(&a) {
+            Some(e) => e.depth < b_depth && self.climb(b, e.depth) == a,
+            None => a == self.base,
+        }
+    }
+
+    /// the lowest common ancestor of `a` and `b`, `None` if either isn't indexed
+    pub fn lca(&self, a: BankKey, b: BankKey) -> Option<BankKey> {
+        let depth = self.entries.get(&a)?.depth.min(self.entries.get(&b)?.depth);
+        let (mut a, mut b) = (self.climb(a, depth), self.climb(b, depth));
+        if a == b {
+            return Some(a);
+        }
+        //both are at the same depth, move them up while they are still apart
+        let mut k = self.entries[&a].jumps.len();
+        while k > 0 {
+            k -= 1;
+            let (ja, jb) = (&self.entries[&a].jumps, &self.entries[&b].jumps);
+            if let (Some(x), Some(y)) = (ja.get(k), jb.get(k)) {
+                if x != y && self.contains(*x) && self.contains(*y) {
+                    a = *x;
+                    b = *y;
+                }
+            }
+        }
+        self.entries[&a].jumps.first().cloned()
+    }
+
+    //the ancestor of `key` at `depth`, it has to be indexed
+    fn climb(&self, mut key: BankKey, depth: u64) -> BankKey {
+        loop {
+            let e = &self.entries[&key];
+            if e.depth <= depth {
+                return key;
+            }
+            let gap = e.depth - depth;
+            let k = (63 - gap.leading_zeros() as usize).min(e.jumps.len() - 1);
+            key = e.jumps[k];
+        }
+    }
+}
+
+#[test]
+fn test_ancestry() {
+    use std::collections::HashSet;
+    //0 -> 1 -> 2 -> ... -> 40, with a branch 5 -> 100 -> 101 and 20 -> 200
+    let mut parents: Vec<(Slot, Slot)> = (1..=40).map(|s| (s, s - 1)).collect();
+    parents.extend([(100, 5), (101, 100), (200, 20)]);
+    let k = |s: Slot| (s, 0);
+    let mut ancestry = Ancestry::new(k(0), k(0));
+    for (s, p) in &parents {
+        ancestry.insert(k(*s), k(*p));
+    }
+    let parent: HashMap<Slot, Slot> = parents.iter().cloned().collect();
+    let fork = |mut s: Slot| {
+        let mut fork = HashSet::from([s]);
+        while let Some(p) = parent.get(&s) {
+            fork.insert(*p);
+            s = *p;
+        }
+        fork
+    };
+    let slots: Vec<Slot> = parents.iter().map(|(s, _)| *s).chain([0]).collect();
+    for a in &slots {
+        for b in &slots {
+            assert_eq!(
+                ancestry.is_ancestor(k(*a), k(*b)),
+                fork(*b).contains(a),
+                "{} {}",
+                a,
+                b
+            );
+            assert_eq!(
+                ancestry.version(k(*b), *a),
+                fork(*b).contains(a).then_some(0)
+            );
+            let keys: HashSet<Slot> = ancestry.fork(k(*a)).into_keys().collect();
+            assert_eq!(keys, fork(*a));
+            let common = fork(*a).intersection(&fork(*b)).max().cloned();
+            assert_eq!(ancestry.lca(k(*a), k(*b)), common.map(k), "{} {}", a, b);
+        }
+    }
+    assert_eq!(ancestry.lca(k(101), k(200)), Some(k(5)));
+    assert_eq!(ancestry.lca(k(39), k(1000)), None);
+
+    //root at 10, the branch at 5 is pruned and 9 stays in every fork
+    ancestry.retain(|k| (10..=40).contains(&k.0) || k.0 == 200, k(9));
+    assert!(!ancestry.contains(k(100)));
+    assert_eq!(
+        ancestry.fork(k(12)),
+        HashMap::from([k(9), k(10), k(11), k(12)])
+    );
+    assert!(ancestry.is_ancestor(k(9), k(200)));
+    assert!(ancestry.is_ancestor(k(10), k(37)));
+    assert!(!ancestry.is_ancestor(k(8), k(37)));
+    assert!(!ancestry.is_ancestor(k(37), k(200)));
+    assert_eq!(ancestry.version(k(37), 9), Some(0));
+    assert_eq!(ancestry.version(k(37), 8), None);
+    assert_eq!(ancestry.lca(k(37), k(200)), Some(k(20)));
+    ancestry.insert(k(41), k(40));
+    assert!(ancestry.is_ancestor(k(11), k(41)));
+    assert_eq!(ancestry.lca(k(41), k(200)), Some(k(20)));
+
+    //a second version of 30 on 28, the slots above it are on one version or the other
+    ancestry.insert((30, 1), k(28));
+    ancestry.insert((31, 1), (30, 1));
+    assert_eq!(ancestry.version((31, 1), 30), Some(1));
+    assert_eq!(ancestry.version((31, 1), 29), None);
+    assert_eq!(ancestry.version(k(41), 30), Some(0));
+    assert!(!ancestry.is_ancestor((30, 1), k(41)));
+    assert_eq!(ancestry.lca((31, 1), k(41)), Some(k(28)));
+}
@@ -0,0 +1,86 @@
+use crate::bank::ID;
+use crate::seed::{self, Stream};
+use crate::snapshot::{corrupt, Reader, SnapshotError, Writer};
+use crate::tower::Slot;
+
+/// Chance that a scheduled leader skips its slot, for modeling offline or slow leaders.
+/// Node ranges override the default rate, later ranges win.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Availability {
+    pub skip_rate: f64,
+    pub ranges: Vec<((ID, ID), f64)>,
+}
+
+impl Availability {
+    /// every leader skips with probability `skip_rate`
+    pub fn uniform(skip_rate: f64) -> Self {
+        assert!((0.0..=1.0).contains(&skip_rate), "invalid skip rate");
+        Availability {
+            skip_rate,
+            ranges: vec![],
+        }
+    }
+
+    /// nodes in `start..end` skip with probability `skip_rate`
+    pub fn set(&mut self, (start, end): (ID, ID), skip_rate: f64) {
+        assert!((0.0..=1.0).contains(&skip_rate), "invalid skip rate");
+        self.ranges.push(((start, end), skip_rate));
+    }
+
+    pub fn skip_rate(&self, id: ID) -> f64 {
+        self.ranges
+            .iter()
+            .rev()
+            .find(|((start, end), _)| (*start..*end).contains(&id))
+            .map_or(self.skip_rate, |(_, rate)| *rate)
+    }
+
+    /// whether `leader` skips `slot`, derived from the master seed
+    pub fn skips(&self, seed: u64, leader: ID, slot: Slot) -> bool {
+        let rate = self.skip_rate(leader);
+        if rate <= 0.0 {
+            return false;
+        }
+        seed::chance(seed::derive(seed, Stream::Availability, slot), rate)
+    }
+
+    pub fn encode(&self, w: &mut Writer) {
+        w.u64(self.skip_rate.to_bits());
+        w.usize(self.ranges.len());
+        for ((start, end), rate) in &self.ranges {
+            w.usize(*start);
+            w.usize(*end);
+            w.u64(rate.to_bits());
+        }
+    }
+
+    pub fn decode(r: &mut Reader) -> Result<Self, SnapshotError> {
+        let rate = |r: &mut Reader| {
+            let rate = f64::from_bits(r.u64()?);
+            if !(0.0..=1.0).contains(&rate) {
+                return corrupt(format!("invalid skip rate {}", rate));
+            }
+            Ok(rate)
+        };
+        let mut availability = Availability::uniform(rate(r)?);
+        for _ in 0..r.count()? {
+            let range = (r.usize()?, r.usize()?);
+            availability.set(range, rate(r)?);
+        }
+        Ok(availability)
+    }
+}
+
+#[test]
+fn test_skip_rates() {
+    let mut availability = Availability::uniform(0.25);
+    availability.set((4, 8), 1.0);
+    availability.set((6, 8), 0.0);
+    assert_eq!(availability.skip_rate(0), 0.25);
+    assert_eq!(availability.skip_rate(4), 1.0);
+    assert_eq!(availability.skip_rate(7), 0.0);
+    assert!((0..100).all(|s| availability.skips(0, 4, s)));
+    assert!((0..100).all(|s| !availability.skips(0, 7, s)));
+    let skipped = (0..10_000).filter(|s| availability.skips(0, 0, *s)).count();
+    assert!((2000..3000).contains(&skipped), "{}", skipped);
+}
@@ -1,83 +1,196 @@
-use crate::node::THRESHOLD;
+use crate::ancestry::Ancestry;
+use crate::config::SimConfig;
+use crate::safety::{sorted, SafetyViolation};
+use crate::seed;
+use crate::slashing::Detector;
+use crate::snapshot::{corrupt, Reader, SnapshotError, Writer};
+use crate::stake::Stake;
 use crate::subcommittee::Subcommittee;
-use crate::tower::{Slot, Tower, Vote};
+use crate::tower::{Slot, Towers, Vote};
 use std::collections::HashMap;
 use std::collections::HashSet;
+use std::sync::Arc;
 
+//default number of nodes, see `SimConfig::new`
 pub const NUM_NODES: usize = 1000;
 pub type ID = usize;
+/// A bank's slot and the `Block::id` of the version of the slot it applied,
+/// each version of a slot is a bank of its own.
+pub type BankKey = (Slot, u64);
 
 pub struct Bank {
-    pub nodes: Vec<Tower>,
+    pub nodes: Towers,
     pub slot: Slot,
+    //`Block::id` of the version of the slot this bank applied
+    pub id: u64,
     pub parent: Slot,
+    pub parent_id: u64,
     pub frozen: bool,
-    pub children: Vec<Slot>,
+    pub children: Vec<BankKey>,
     pub subcom: Subcommittee,
+    pub config: Arc<SimConfig>,
 }
 
+#[derive(Clone, Debug, PartialEq)]
 pub struct Block {
     pub slot: Slot,
     pub parent: Slot,
+    //`Block::id` of the version of `parent` this block builds on
+    pub parent_id: u64,
+    pub producer: ID,
     pub votes: Vec<(ID, Vec<Vote>)>,
 }
 
-impl Bank {
-    pub fn zero() -> Self {
-        let mut nodes = vec![];
-        for _ in 0..NUM_NODES {
-            nodes.push(Tower::default());
+impl Block {
+    /// Identifies this version of the block, a producer that sends two versions
+    /// of a slot equivocates.
+    pub fn id(&self) -> u64 {
+        let mut id =
+            seed::hash(self.slot ^ seed::hash(self.parent ^ seed::hash(self.producer as u64)));
+        id = seed::hash(id ^ self.parent_id);
+        for (node, votes) in &self.votes {
+            id = seed::hash(id ^ *node as u64);
+            for v in votes {
+                id = seed::hash(id ^ v.slot);
+            }
         }
+        id
+    }
+}
+
+impl Bank {
+    pub fn zero(config: Arc<SimConfig>) -> Self {
         Bank {
             frozen: true,
-            nodes,
+            nodes: Towers::new(config.num_nodes(), config.depth),
             slot: 0,
+            id: 0,
             parent: 0,
-            subcom: Subcommittee::default(),
+            parent_id: 0,
+            subcom: Subcommittee::new(&config),
             children: vec![],
+            config,
+        }
+    }
+    pub fn encode(&self, w: &mut Writer) {
+        w.u64(self.slot);
+        w.u64(self.id);
+        w.u64(self.parent);
+        w.u64(self.parent_id);
+        w.bool(self.frozen);
+        w.pairs(&self.children);
+        self.subcom.encode(w);
+        for t in self.nodes.iter() {
+            w.tower(t);
         }
     }
-    pub fn child(&mut self, slot: Slot) -> Self {
+
+    pub fn decode(r: &mut Reader, config: &Arc<SimConfig>) -> Result<Self, SnapshotError> {
+        let slot = r.u64()?;
+        let id = r.u64()?;
+        let parent = r.u64()?;
+        let parent_id = r.u64()?;
+        let frozen = r.bool()?;
+        let children = r.pairs()?;
+        let subcom = Subcommittee::decode(r, config.num_nodes())?;
+        let nodes = (0..config.num_nodes())
+            .map(|_| r.tower())
+            .collect::<Result<Towers, _>>()?;
+        if parent > slot {
+            return corrupt(format!("bank {} has parent {}", slot, parent));
+        }
+        Ok(Bank {
+            nodes,
+            slot,
+            id,
+            parent,
+            parent_id,
+            frozen,
+            children,
+            subcom,
+            config: config.clone(),
+        })
+    }
+
+    pub fn key(&self) -> BankKey {
+        (self.slot, self.id)
+    }
+
+    pub fn parent_key(&self) -> BankKey {
+        (self.parent, self.parent_id)
+    }
+
+    /// the bank for `slot` on top of this one, `Forks` adds it to `children` once it is applied
+    pub fn child(&self, slot: Slot) -> Self {
         assert!(self.frozen);
         let mut b = Bank {
             nodes: self.nodes.clone(),
             slot,
+            id: 0,
             parent: self.slot,
+            parent_id: self.id,
             children: vec![],
             subcom: self.subcom.child(),
             frozen: false,
+            config: self.config.clone(),
         };
-        println!("INIT CHILD {} {}", self.slot, slot);
-        b.subcom.init_child(&self.subcom);
-        self.children.push(slot);
+        b.subcom.init_child(&self.subcom, &self.config);
         b
     }
 
-    pub fn apply(&mut self, block: &Block, fork: &HashSet<Slot>) {
+    /// apply the votes of `block`, every vote in the fork is also checked by `detector`,
+    /// `ancestry` indexes the parent but not this bank yet
+    pub fn apply(
+        &mut self,
+        block: &Block,
+        ancestry: &Ancestry,
+        detector: &mut Detector,
+    ) -> Result<(), SafetyViolation> {
         assert!(!self.frozen);
         assert_eq!(self.slot, block.slot);
-        assert_eq!(self.parent, block.parent);
-        let min = *fork.iter().min().unwrap();
+        assert_eq!(self.parent_key(), (block.parent, block.parent_id));
+        self.id = block.id();
+        let (slot, bank_id, parent) = (self.slot, self.id, self.parent_key());
+        //the version of each slot in the fork
+        let version = |s: Slot| {
+            if s == slot {
+                Some(bank_id)
+            } else {
+                ancestry.version(parent, s)
+            }
+        };
+        let in_fork = |s: Slot| version(s).is_some();
+        let min = ancestry.base().0;
         for (id, votes) in &block.votes {
             for v in votes {
                 if v.slot < min {
                     //skip votes that are too old, these are comming from a new subcommittee node
                     continue;
                 }
-                assert!(
-                    fork.contains(&v.slot),
-                    "proposed vote is not in the bank's fork {:?} {}",
-                    fork,
-                    v.slot
-                );
-                let _e = self.nodes[*id].apply(v);
+                if !in_fork(v.slot) {
+                    let mut fork = ancestry.fork(parent);
+                    fork.insert(slot, self.id);
+                    return Err(SafetyViolation::VoteNotInFork {
+                        slot,
+                        node: *id,
+                        vote: v.slot,
+                        fork: sorted(fork.keys()),
+                    });
+                }
+                detector.observe(slot, *id, v.slot, version(v.slot).unwrap(), in_fork, min);
+                //towers that don't change stay shared with the parent bank
+                if self.nodes[*id].accepts(v) {
+                    let _e = self.nodes[*id].apply(v);
+                }
             }
         }
         let primary = self.primary_super_root().slot;
         let secondary = self.secondary_super_root().slot;
         let oc_slot = self.oc_slots().into_iter().max().unwrap_or(0);
-        self.subcom.freeze(primary, secondary, self.parent == oc_slot);
+        self.subcom
+            .freeze(primary, secondary, self.parent == oc_slot);
         self.frozen = true;
+        Ok(())
     }
 
     pub fn oc_slots(&self) -> HashSet<Slot> {
@@ -86,7 +199,7 @@ impl Bank {
         primary.intersection(&secondary).cloned().collect()
     }
     pub fn check_group_oc(&self, group: &HashSet<ID>) -> HashSet<Slot> {
-        let mut confs: HashMap<Slot, usize> = HashMap::new();
+        let mut confs: HashMap<Slot, Stake> = HashMap::new();
 
         for p in group {
             let s = self.nodes[*p]
@@ -94,54 +207,72 @@ impl Bank {
                 .front()
                 .unwrap_or(&self.nodes[*p].root)
                 .slot;
-            let e = confs.entry(s).or_insert(0);
-            *e = *e + 1;
+            *confs.entry(s).or_insert(0) += self.config.stakes.get(*p);
         }
 
+        let group_stake = self.config.stakes.sum(group);
         confs
             .iter()
-            .filter(|(_k, v)| **v > (2 * group.len()) / 3)
+            .filter(|(_k, v)| **v > (2 * group_stake) / 3)
             .map(|(k, _v)| *k)
             .collect()
     }
 
-    pub fn primary_calc_threshold_slot(&self, mult: u64, vote: &Vote) -> usize {
-        let count: usize = self
+    pub fn primary_calc_threshold_slot(&self, mult: u64, vote: &Vote) -> Stake {
+        let stake: Stake = self
             .subcom
             .primary
             .iter()
             .map(|p| {
                 let n = &self.nodes[*p];
+                let stake = self.config.stakes.get(*p);
                 //alredy rooted
                 if n.root.slot >= vote.slot {
-                    return 1;
+                    return stake;
                 }
                 for v in &n.votes {
-                    if vote.lockout == 1 << THRESHOLD && v.slot >= vote.slot {
-                        return 1;
+                    if vote.lockout == 1 << self.config.threshold && v.slot >= vote.slot {
+                        return stake;
                     }
                     //check if the node has a higher vote with at least 1/2 the lockout
                     if v.slot >= vote.slot
                         && (v.slot + (mult * v.lockout)) >= (vote.slot + vote.lockout)
                     {
-                        return 1;
+                        return stake;
                     }
                 }
                 0
             })
             .sum();
-        count
+        stake
     }
 
     pub fn primary_threshold_slot(&self, vote: &Vote) -> bool {
-        self.primary_calc_threshold_slot(1 << 4, vote) > (2 * self.subcom.primary.len()) / 3
+        self.primary_calc_threshold_slot(1 << 4, vote) > self.primary_threshold_stake()
+    }
+
+    //stake that must be exceeded to pass the threshold check
+    pub fn primary_threshold_stake(&self) -> Stake {
+        let primary_stake = self.config.stakes.sum(&self.subcom.primary);
+        (2 * primary_stake) / 3
     }
 
     pub fn group_super_root(&self, set: &HashSet<ID>) -> Vote {
-        let mut roots: Vec<_> = set.iter().map(|p| self.nodes[*p].root).collect();
-        roots.sort_by_key(|x| x.slot);
-        //2/3 of the nodes are at least at this root
-        roots[self.subcom.primary.len() / 3]
+        let mut roots: Vec<_> = set
+            .iter()
+            .map(|p| (self.nodes[*p].root, self.config.stakes.get(*p)))
+            .collect();
+        roots.sort_by_key(|(x, _)| x.slot);
+        //2/3 of the stake is at least at this root
+        let group_stake = self.config.stakes.sum(set);
+        let mut stake = 0;
+        for (root, s) in &roots {
+            stake += s;
+            if stake > group_stake / 3 {
+                return *root;
+            }
+        }
+        roots.last().unwrap().0
     }
 
     pub fn primary_super_root(&self) -> Vote {
@@ -163,16 +294,16 @@ impl Bank {
         roots[0]
     }
 
-    //get the latest votes from each node
-    pub fn primary_latest_votes(&self, latest_votes: &mut HashMap<ID, Slot>) {
-        for p in self.subcom.primary.iter() {
-            let n = &self.nodes[*p];
-            let latest = n.latest_vote().unwrap_or(&n.root);
-            let e = latest_votes.entry(*p).or_insert(latest.slot);
-            if *e < latest.slot {
-                *e = latest.slot;
-            }
-        }
+    //the latest vote of each primary node
+    pub fn primary_latest_votes(&self) -> Vec<(ID, Slot)> {
+        self.subcom
+            .primary
+            .iter()
+            .map(|p| {
+                let n = &self.nodes[*p];
+                (*p, n.latest_vote().unwrap_or(&n.root).slot)
+            })
+            .collect()
     }
     pub fn check_primary(&self, id: ID) -> bool {
         self.subcom.primary.contains(&id)
@@ -0,0 +1,172 @@
+use crate::availability::Availability;
+use crate::bank::NUM_NODES;
+use crate::latency::Latency;
+use crate::leader_schedule::{LEADER_EPOCH, LEADER_WINDOW};
+use crate::mempool::VotePolicy;
+use crate::node::THRESHOLD;
+use crate::snapshot::{corrupt, Reader, SnapshotError, Writer};
+use crate::stake::{Stake, Stakes};
+use crate::subcommittee::{SUBCOMMITTEE_EPOCH, SUBCOMMITTEE_SIZE};
+use crate::tower::DEPTH;
+
+/// Simulation parameters, the module constants are the defaults.
+/// The number of nodes is the size of the stake table.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SimConfig {
+    //max number of votes in a tower, a vote is rooted at 2^depth lockout
+    pub depth: usize,
+    //lockouts at or above 2^threshold must pass the threshold check
+    pub threshold: usize,
+    //number of samples drawn for each subcommittee
+    pub subcommittee_size: usize,
+    //number of super root increases per subcommittee epoch
+    pub subcommittee_epoch: usize,
+    //consecutive slots produced by the same leader
+    pub leader_window: usize,
+    //slots per leader schedule
+    pub leader_epoch: usize,
+    //sample leaders by stake instead of uniformly
+    pub stake_weighted_leaders: bool,
+    //chance that a leader skips its slot
+    pub availability: Availability,
+    //message delays between nodes
+    pub latency: Latency,
+    //how votes travel and land in blocks
+    pub votes: VotePolicy,
+    pub stakes: Stakes,
+    //master seed, see `seed`
+    pub seed: u64,
+}
+
+impl Default for SimConfig {
+    fn default() -> Self {
+        Self::new(NUM_NODES)
+    }
+}
+
+impl SimConfig {
+    pub fn new(num_nodes: usize) -> Self {
+        SimConfig {
+            depth: DEPTH,
+            threshold: THRESHOLD,
+            subcommittee_size: SUBCOMMITTEE_SIZE,
+            subcommittee_epoch: SUBCOMMITTEE_EPOCH,
+            leader_window: LEADER_WINDOW,
+            leader_epoch: LEADER_EPOCH,
+            stake_weighted_leaders: true,
+            availability: Availability::default(),
+            latency: Latency::default(),
+            votes: VotePolicy::default(),
+            stakes: Stakes::uniform(num_nodes),
+            seed: 0,
+        }
+    }
+
+    pub fn num_nodes(&self) -> usize {
+        self.stakes.len()
+    }
+
+    pub fn encode(&self, w: &mut Writer) {
+        w.usize(self.depth);
+        w.usize(self.threshold);
+        w.usize(self.subcommittee_size);
+        w.usize(self.subcommittee_epoch);
+        w.usize(self.leader_window);
+        w.usize(self.leader_epoch);
+        w.bool(self.stake_weighted_leaders);
+        self.availability.encode(w);
+        self.latency.encode(w);
+        self.votes.encode(w);
+        let stakes: Vec<Stake> = (0..self.num_nodes())
+            .map(|id| self.stakes.get(id))
+            .collect();
+        w.u64s(&stakes);
+        w.u64(self.seed);
+    }
+
+    pub fn decode(r: &mut Reader) -> Result<Self, SnapshotError> {
+        let depth = r.depth()?;
+        let threshold = r.usize()?;
+        let subcommittee_size = r.usize()?;
+        let subcommittee_epoch = r.usize()?;
+        let leader_window = r.usize()?;
+        let leader_epoch = r.usize()?;
+        let stake_weighted_leaders = r.bool()?;
+        let availability = Availability::decode(r)?;
+        let latency = Latency::decode(r)?;
+        let votes = VotePolicy::decode(r)?;
+        let stakes = r.u64s()?;
+        //`Stakes::new` asserts what a corrupt snapshot can't be trusted with
+        let total = stakes
+            .iter()
+            .try_fold(0, |total: Stake, s| total.checked_add(*s));
+        if matches!(total, None | Some(0)) {
+            return corrupt("invalid stake table");
+        }
+        let config = SimConfig {
+            depth,
+            threshold,
+            subcommittee_size,
+            subcommittee_epoch,
+            leader_window,
+            leader_epoch,
+            stake_weighted_leaders,
+            availability,
+            latency,
+            votes,
+            stakes: Stakes::new(stakes),
+            seed: r.u64()?,
+        };
+        match config.validate() {
+            Ok(()) => Ok(config),
+            Err(e) => corrupt(format!("invalid config: {}", e)),
+        }
+    }
+
+    /// Checks the parameters a run can't start with. Scenario files, the command
+    /// line and snapshots all go through it.
+    pub fn validate(&self) -> Result<(), String> {
+        //lockouts go up to 2^depth
+        if self.depth == 0 || self.depth >= 64 {
+            return Err(format!(
+                "depth must be between 1 and 63, got {}",
+                self.depth
+            ));
+        }
+        if self.threshold > self.depth {
+            return Err(format!(
+                "threshold must be at most the depth {}, got {}",
+                self.depth, self.threshold
+            ));
+        }
+        if self.subcommittee_size == 0 || self.subcommittee_epoch == 0 {
+            return Err("subcommittee_size and subcommittee_epoch must be at least 1".to_string());
+        }
+        if self.leader_window == 0 || self.leader_epoch < self.leader_window {
+            return Err("leader_epoch must be at least one non-empty leader_window".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_decode_invalid_stakes() {
+    let mut w = Writer::new();
+    SimConfig::new(2).encode(&mut w);
+    //the stake table and the seed are last
+    let tail = |stakes: &[Stake]| {
+        let mut w = Writer::new();
+        let header = w.buf.len();
+        w.u64s(stakes);
+        w.u64(0);
+        w.buf.split_off(header)
+    };
+    let prefix = w.buf.len() - tail(&[1, 1]).len();
+    assert!(SimConfig::decode(&mut Reader::new(&w.buf).unwrap()).is_ok());
+    for stakes in [&[][..], &[0, 0], &[u64::MAX, 1]] {
+        let mut buf = w.buf[..prefix].to_vec();
+        buf.extend(tail(stakes));
+        let rv = SimConfig::decode(&mut Reader::new(&buf).unwrap());
+        assert!(matches!(rv, Err(SnapshotError::Corrupt(_))), "{:?}", stakes);
+    }
+}
@@ -0,0 +1,237 @@
+//! Which nodes can reach each other in a slot.
+//!
+//! Links are directed, a node can reach another without being reachable from
+//! it, and two nodes that can't reach each other may still share a neighbour.
+//! Every message on a link is dropped with the link's loss probability.
+use crate::bank::ID;
+use crate::forks::Forks;
+use crate::seed::{self, Stream};
+use crate::stake::Stakes;
+use crate::tower::Slot;
+use rand::seq::SliceRandom;
+use std::collections::BTreeSet;
+
+//nodes start..end
+pub type Nodes = (ID, ID);
+
+/// The members of a partition, a range of node ids or any set of them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum NodeSet {
+    Range(ID, ID),
+    Ids(BTreeSet<ID>),
+}
+
+impl From<Nodes> for NodeSet {
+    fn from((start, end): Nodes) -> Self {
+        NodeSet::Range(start, end)
+    }
+}
+
+impl NodeSet {
+    pub fn ids(ids: impl IntoIterator<Item = ID>) -> Self {
+        NodeSet::Ids(ids.into_iter().collect())
+    }
+
+    /// the primary subcommittee of the newest bank
+    pub fn primary(forks: &Forks) -> Self {
+        Self::ids(forks.latest_primary())
+    }
+
+    /// Random nodes that together hold at least `fraction` of the stake. The
+    /// same seed and `index` pick the same nodes.
+    pub fn stake(seed: u64, stakes: &Stakes, fraction: f64, index: u64) -> Self {
+        assert!((0.0..=1.0).contains(&fraction), "invalid stake fraction");
+        let mut ids: Vec<ID> = (0..stakes.len()).collect();
+        ids.shuffle(&mut seed::rng(seed, Stream::Partitions, index));
+        let target = (stakes.total() as f64 * fraction).ceil() as u64;
+        let mut picked = BTreeSet::new();
+        let mut stake = 0;
+        for id in ids {
+            if stake >= target {
+                break;
+            }
+            stake += stakes.get(id);
+            picked.insert(id);
+        }
+        NodeSet::Ids(picked)
+    }
+
+    /// the nodes out of `num_nodes` that are in none of `sets`
+    pub fn rest(sets: &[NodeSet], num_nodes: usize) -> Self {
+        Self::ids((0..num_nodes).filter(|id| !sets.iter().any(|s| s.contains(*id))))
+    }
+
+    pub fn contains(&self, id: ID) -> bool {
+        match self {
+            NodeSet::Range(start, end) => (*start..*end).contains(&id),
+            NodeSet::Ids(ids) => ids.contains(&id),
+        }
+    }
+
+    /// the lowest member
+    pub fn first(&self) -> Option<ID> {
+        match self {
+            NodeSet::Range(start, end) => (start < end).then_some(*start),
+            NodeSet::Ids(ids) => ids.first().copied(),
+        }
+    }
+
+    /// one past the highest member
+    pub fn end(&self) -> ID {
+        match self {
+            NodeSet::Range(_, end) => *end,
+            NodeSet::Ids(ids) => ids.last().map_or(0, |id| id + 1),
+        }
+    }
+}
+
+/// what a message carries, messages of different kinds are lost independently
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    Block,
+    Vote,
+}
+
+pub trait Connectivity {
+    /// whether `id` votes this slot
+    fn active(&self, id: ID) -> bool;
+    /// the loss probability of the link from `from` to `to`, `None` if there is no link
+    fn link(&self, from: ID, to: ID) -> Option<f64>;
+
+    /// whether a message sent in `slot` reaches `to`, derived from the master seed
+    fn reaches(&self, seed: u64, kind: Kind, from: ID, to: ID, slot: Slot) -> bool {
+        match self.link(from, to) {
+            None => false,
+            Some(loss) if loss <= 0.0 => true,
+            Some(loss) => {
+                let index = seed::hash(seed::hash(slot ^ seed::hash(from as u64)) ^ to as u64);
+                !seed::chance(seed::derive(seed, Stream::Loss, index ^ kind as u64), loss)
+            }
+        }
+    }
+}
+
+/// Node sets, only the active ones are live. The nodes of every active set
+/// reach each other without loss.
+#[derive(Clone, Copy, Debug)]
+pub struct Partitions<'a> {
+    pub partitions: &'a [NodeSet],
+    pub active: &'a [bool],
+}
+
+impl Connectivity for Partitions<'_> {
+    fn active(&self, id: ID) -> bool {
+        if self.partitions.is_empty() {
+            return true;
+        }
+        self.active
+            .iter()
+            .zip(self.partitions)
+            .any(|(r, p)| *r && p.contains(id))
+    }
+
+    fn link(&self, from: ID, to: ID) -> Option<f64> {
+        (self.active(from) && self.active(to)).then_some(0.0)
+    }
+}
+
+/// A directed graph where every node is live. Links between node ranges
+/// override the default link, later links win. A node always reaches itself.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Graph {
+    //`None` cuts the link
+    pub default: Option<f64>,
+    pub links: Vec<(Nodes, Nodes, Option<f64>)>,
+}
+
+impl Default for Graph {
+    fn default() -> Self {
+        Self::full(0.0)
+    }
+}
+
+impl Graph {
+    /// every node reaches every other node with `loss`
+    pub fn full(loss: f64) -> Self {
+        assert!((0.0..=1.0).contains(&loss), "invalid loss");
+        Graph {
+            default: Some(loss),
+            links: vec![],
+        }
+    }
+
+    /// messages from `from` to `to` are dropped with `loss`
+    pub fn connect(&mut self, from: Nodes, to: Nodes, loss: f64) {
+        assert!((0.0..=1.0).contains(&loss), "invalid loss");
+        self.links.push((from, to, Some(loss)));
+    }
+
+    /// messages from `from` never reach `to`
+    pub fn cut(&mut self, from: Nodes, to: Nodes) {
+        self.links.push((from, to, None));
+    }
+}
+
+impl Connectivity for Graph {
+    fn active(&self, _id: ID) -> bool {
+        true
+    }
+
+    fn link(&self, from: ID, to: ID) -> Option<f64> {
+        if from == to {
+            return Some(0.0);
+        }
+        self.links
+            .iter()
+            .rev()
+            .find(|((fs, fe), (ts, te), _)| (*fs..*fe).contains(&from) && (*ts..*te).contains(&to))
+            .map_or(self.default, |(_, _, link)| *link)
+    }
+}
+
+#[test]
+fn test_asymmetric_lossy_links() {
+    let mut graph = Graph::full(0.0);
+    //0 hears from 1 but 1 doesn't hear from 0, 2 reaches 1 and 3 but 1 and 3 are cut
+    graph.cut((0, 1), (1, 2));
+    graph.cut((1, 2), (3, 4));
+    graph.cut((3, 4), (1, 2));
+    graph.connect((4, 8), (0, 8), 0.5);
+    assert_eq!(graph.link(1, 0), Some(0.0));
+    assert_eq!(graph.link(0, 1), None);
+    assert_eq!(graph.link(2, 1), Some(0.0));
+    assert_eq!(graph.link(2, 3), Some(0.0));
+    assert_eq!(graph.link(1, 3), None);
+    assert_eq!(graph.link(5, 5), Some(0.0));
+    assert!(!(0..100).any(|s| graph.reaches(0, Kind::Block, 0, 1, s)));
+    let reached = (0..10_000)
+        .filter(|s| graph.reaches(0, Kind::Block, 5, 0, *s))
+        .count();
+    assert!((4000..6000).contains(&reached), "{}", reached);
+
+    let partitions = Partitions {
+        partitions: &[NodeSet::ids([0, 3]), (1, 3).into()],
+        active: &[true, false],
+    };
+    assert_eq!(partitions.link(0, 3), Some(0.0));
+    assert_eq!(partitions.link(0, 2), None);
+    assert!(!partitions.active(1));
+}
+
+#[test]
+fn test_node_sets() {
+    let stakes = Stakes::new(vec![10, 20, 30, 40]);
+    let third = NodeSet::stake(0, &stakes, 0.33, 0);
+    assert_eq!(third, NodeSet::stake(0, &stakes, 0.33, 0));
+    let ids = match &third {
+        NodeSet::Ids(ids) => ids.clone(),
+        _ => unreachable!(),
+    };
+    assert!(stakes.sum(&ids) >= 33);
+    let rest = NodeSet::rest(&[third.clone(), (3, 4).into()], 4);
+    assert!((0..4).all(|id| [&third, &rest].iter().any(|s| s.contains(id)) || id == 3));
+    assert!((0..4).all(|id| !(third.contains(id) && rest.contains(id))));
+    assert_eq!(NodeSet::ids([5, 2]).first(), Some(2));
+    assert_eq!(NodeSet::ids([5, 2]).end(), 6);
+    assert_eq!(NodeSet::Range(3, 3).first(), None);
+}
@@ -0,0 +1,366 @@
+use crate::bank::ID;
+use crate::node::{Reason, Refusal};
+use crate::slashing::Evidence;
+use crate::subcommittee::Phase;
+use crate::tower::{Slot, Vote};
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    BlockApplied {
+        slot: Slot,
+        parent: Slot,
+        producer: ID,
+        votes: usize,
+    },
+    VoteCast {
+        node: ID,
+        vote: Vote,
+        root: Slot,
+    },
+    //refusals from nodes outside the subcommittee are only counted in the tally
+    VoteRefused {
+        node: ID,
+        reason: Reason,
+    },
+    //outcome of every node that voted this slot
+    VoteTally {
+        slot: Slot,
+        voted: usize,
+        refused: Vec<(Refusal, usize)>,
+    },
+    //the vote transaction of a node was lost on the way to the leader
+    VoteLost {
+        slot: Slot,
+        node: ID,
+    },
+    //delays are the number of vote transactions that landed in the block per
+    //slots since they were sent, stale transactions were dropped from the pool
+    VotesLanded {
+        slot: Slot,
+        delays: Vec<(Slot, usize)>,
+        stale: usize,
+    },
+    //the lowest primary root moved, max_root is the highest root of any node in the bank
+    //and rooted are the newly rooted slots
+    RootAdvanced {
+        slot: Slot,
+        from: Slot,
+        to: Slot,
+        max_root: Slot,
+        rooted: Vec<Slot>,
+    },
+    //the scheduled leader didn't produce a block
+    SlotSkipped {
+        slot: Slot,
+        leader: ID,
+    },
+    //another version of a slot that already has a bank, it is dropped
+    DuplicateBlock {
+        slot: Slot,
+        producer: ID,
+        //`Block::id` of the new version and of one that was applied before it
+        id: u64,
+        first_id: u64,
+        parent: Slot,
+        first_parent: Slot,
+    },
+    //provable misbehavior in the votes of a block, see `slashing`
+    Slashable {
+        evidence: Evidence,
+    },
+    SuperRootAdvanced {
+        slot: Slot,
+        super_root: Slot,
+    },
+    SubcommitteeRotated {
+        slot: Slot,
+        phase: Phase,
+        members: Vec<ID>,
+    },
+    OcObserved {
+        slot: Slot,
+        oc_slots: Vec<Slot>,
+    },
+    //orphaned are the pruned banks that were never rooted
+    Gc {
+        root: Slot,
+        kept: usize,
+        pruned: usize,
+        orphaned: usize,
+    },
+}
+
+impl Event {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::BlockApplied { .. } => "block_applied",
+            Event::VoteCast { .. } => "vote_cast",
+            Event::VoteRefused { .. } => "vote_refused",
+            Event::VoteTally { .. } => "vote_tally",
+            Event::VoteLost { .. } => "vote_lost",
+            Event::VotesLanded { .. } => "votes_landed",
+            Event::RootAdvanced { .. } => "root_advanced",
+            Event::SlotSkipped { .. } => "slot_skipped",
+            Event::DuplicateBlock { .. } => "duplicate_block",
+            Event::Slashable { .. } => "slashable",
+            Event::SuperRootAdvanced { .. } => "super_root_advanced",
+            Event::SubcommitteeRotated { .. } => "subcommittee_rotated",
+            Event::OcObserved { .. } => "oc_observed",
+            Event::Gc { .. } => "gc",
+        }
+    }
+
+    /// single line json object
+    pub fn to_json(&self) -> String {
+        let mut s = format!("{{\"event\":\"{}\"", self.name());
+        let fields: Vec<(&str, String)> = match self {
+            Event::BlockApplied {
+                slot,
+                parent,
+                producer,
+                votes,
+            } => vec![
+                ("slot", slot.to_string()),
+                ("parent", parent.to_string()),
+                ("producer", producer.to_string()),
+                ("votes", votes.to_string()),
+            ],
+            Event::VoteCast { node, vote, root } => vec![
+                ("node", node.to_string()),
+                ("slot", vote.slot.to_string()),
+                ("lockout", vote.lockout.to_string()),
+                ("root", root.to_string()),
+            ],
+            Event::VoteRefused { node, reason } => {
+                let mut fields = vec![
+                    ("node", node.to_string()),
+                    ("slot", reason.slot().to_string()),
+                    ("reason", format!("\"{}\"", reason.kind().name())),
+                ];
+                match reason {
+                    Reason::NotInSubcommittee { .. } => (),
+                    Reason::AlreadyVoted { latest, .. } => {
+                        fields.push(("latest", latest.to_string()))
+                    }
+                    Reason::LockedOut { locked, .. } => {
+                        fields.push(("locked_slot", locked.slot.to_string()));
+                        fields.push(("locked_lockout", locked.lockout.to_string()));
+                    }
+                    Reason::Threshold {
+                        vote,
+                        stake,
+                        required,
+                        ..
+                    } => {
+                        fields.push(("vote_slot", vote.slot.to_string()));
+                        fields.push(("vote_lockout", vote.lockout.to_string()));
+                        fields.push(("stake", stake.to_string()));
+                        fields.push(("required", required.to_string()));
+                    }
+                    Reason::OptimisticConfirmation {
+                        last_vote,
+                        stake,
+                        required,
+                        ..
+                    } => {
+                        fields.push(("last_vote", last_vote.to_string()));
+                        fields.push(("stake", stake.to_string()));
+                        fields.push(("required", required.to_string()));
+                    }
+                }
+                fields
+            }
+            Event::VoteTally {
+                slot,
+                voted,
+                refused,
+            } => {
+                let mut fields = vec![("slot", slot.to_string()), ("voted", voted.to_string())];
+                for (r, count) in refused {
+                    fields.push((r.name(), count.to_string()));
+                }
+                fields
+            }
+            Event::VoteLost { slot, node } => {
+                vec![("slot", slot.to_string()), ("node", node.to_string())]
+            }
+            Event::VotesLanded {
+                slot,
+                delays,
+                stale,
+            } => {
+                let delays: Vec<_> = delays
+                    .iter()
+                    .map(|(delay, count)| format!("[{},{}]", delay, count))
+                    .collect();
+                vec![
+                    ("slot", slot.to_string()),
+                    ("delays", json_array(&delays)),
+                    ("stale", stale.to_string()),
+                ]
+            }
+            Event::RootAdvanced {
+                slot,
+                from,
+                to,
+                max_root,
+                rooted,
+            } => vec![
+                ("slot", slot.to_string()),
+                ("from", from.to_string()),
+                ("to", to.to_string()),
+                ("max_root", max_root.to_string()),
+                ("rooted", json_array(rooted)),
+            ],
+            Event::SlotSkipped { slot, leader } => {
+                vec![("slot", slot.to_string()), ("leader", leader.to_string())]
+            }
+            Event::DuplicateBlock {
+                slot,
+                producer,
+                id,
+                first_id,
+                parent,
+                first_parent,
+            } => vec![
+                ("slot", slot.to_string()),
+                ("producer", producer.to_string()),
+                ("id", id.to_string()),
+                ("first_id", first_id.to_string()),
+                ("parent", parent.to_string()),
+                ("first_parent", first_parent.to_string()),
+            ],
+            Event::Slashable { evidence } => {
+                let mut fields = vec![
+                    ("slot", evidence.slot().to_string()),
+                    ("node", evidence.node().to_string()),
+                    ("kind", format!("\"{}\"", evidence.name())),
+                ];
+                match evidence {
+                    Evidence::LockoutViolation { locked, vote, .. } => {
+                        fields.push(("locked_slot", locked.slot.to_string()));
+                        fields.push(("locked_lockout", locked.lockout.to_string()));
+                        fields.push(("vote", vote.to_string()));
+                    }
+                    Evidence::DoubleVote {
+                        vote, first_id, id, ..
+                    } => {
+                        fields.push(("vote", vote.to_string()));
+                        fields.push(("first_id", first_id.to_string()));
+                        fields.push(("id", id.to_string()));
+                    }
+                    Evidence::Equivocation { first_id, id, .. } => {
+                        fields.push(("first_id", first_id.to_string()));
+                        fields.push(("id", id.to_string()));
+                    }
+                }
+                fields
+            }
+            Event::SuperRootAdvanced { slot, super_root } => vec![
+                ("slot", slot.to_string()),
+                ("super_root", super_root.to_string()),
+            ],
+            Event::SubcommitteeRotated {
+                slot,
+                phase,
+                members,
+            } => vec![
+                ("slot", slot.to_string()),
+                ("phase", format!("\"{:?}\"", phase)),
+                ("members", json_array(members)),
+            ],
+            Event::OcObserved { slot, oc_slots } => vec![
+                ("slot", slot.to_string()),
+                ("oc_slots", json_array(oc_slots)),
+            ],
+            Event::Gc {
+                root,
+                kept,
+                pruned,
+                orphaned,
+            } => vec![
+                ("root", root.to_string()),
+                ("kept", kept.to_string()),
+                ("pruned", pruned.to_string()),
+                ("orphaned", orphaned.to_string()),
+            ],
+        };
+        for (k, v) in fields {
+            let _ = write!(s, ",\"{}\":{}", k, v);
+        }
+        s.push('}');
+        s
+    }
+}
+
+fn json_array<T: ToString>(vals: &[T]) -> String {
+    let vals: Vec<_> = vals.iter().map(|v| v.to_string()).collect();
+    format!("[{}]", vals.join(","))
+}
+
+pub trait EventSink {
+    fn record(&mut self, event: &Event);
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// drops every event, for benchmarks
+#[derive(Default)]
+pub struct NullSink;
+
+impl EventSink for NullSink {
+    fn record(&mut self, _event: &Event) {}
+}
+
+/// one json object per line
+pub struct JsonLinesSink<W: Write> {
+    out: W,
+    error: Option<io::Error>,
+}
+
+impl<W: Write> JsonLinesSink<W> {
+    pub fn new(out: W) -> Self {
+        JsonLinesSink { out, error: None }
+    }
+}
+
+impl<W: Write> EventSink for JsonLinesSink<W> {
+    fn record(&mut self, event: &Event) {
+        if self.error.is_some() {
+            return;
+        }
+        if let Err(e) = writeln!(self.out, "{}", event.to_json()) {
+            self.error = Some(e);
+        }
+    }
+    //reports the first write error
+    fn flush(&mut self) -> io::Result<()> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        self.out.flush()
+    }
+}
+
+/// keeps every event in memory, clones share the same buffer
+/// so a test can keep a handle after giving the sink to the network
+#[derive(Clone, Default)]
+pub struct MemorySink {
+    events: Arc<Mutex<Vec<Event>>>,
+}
+
+impl MemorySink {
+    pub fn events(&self) -> Vec<Event> {
+        self.events.lock().unwrap().clone()
+    }
+}
+
+impl EventSink for MemorySink {
+    fn record(&mut self, event: &Event) {
+        self.events.lock().unwrap().push(event.clone());
+    }
+}
@@ -1,99 +1,357 @@
-use crate::bank::{Bank, Block, ID};
+use crate::ancestry::Ancestry;
+use crate::bank::{Bank, BankKey, Block, ID};
+use crate::config::SimConfig;
+use crate::events::Event;
+use crate::safety::{sorted, SafetyViolation};
+use crate::slashing::Detector;
+use crate::snapshot::{corrupt, Reader, SnapshotError, Writer};
+use crate::stake::{Stake, Stakes};
 use crate::subcommittee::Phase;
 use crate::tower::{Slot, Vote};
+use std::collections::BTreeMap;
 use std::collections::HashMap;
 use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+
+//forks kept in the `compute_fork` cache, the nodes mostly look up the newest slots
+const MAX_PATHS: usize = 64;
+
+/// The slots of a fork and the version of each, see `Forks::compute_fork`
+pub type Fork = HashMap<Slot, u64>;
 
 pub struct Forks {
-    pub fork_map: HashMap<Slot, Bank>,
-    pub primary_fork_weights: HashMap<Slot, usize>,
+    pub fork_map: HashMap<BankKey, Bank>,
+    pub primary_fork_weights: HashMap<BankKey, Stake>,
     pub lowest_root: Vote,
-    pub roots: HashSet<Slot>,
+    //the rooted slots and the version of each
+    pub roots: HashMap<Slot, u64>,
+    pub config: Arc<SimConfig>,
+    //checks the votes of every applied block
+    pub detector: Detector,
+    //events since the last drain
+    pub events: Vec<Event>,
+    //keeps `primary_fork_weights` up to date as banks are added and pruned
+    fork_choice: ForkChoice,
+    pub ancestry: Ancestry,
+    //forks computed since the last gc, gc changes the bottom of every fork, the
+    //oldest slots are evicted past `MAX_PATHS` so a stalled root doesn't grow it
+    paths: Mutex<BTreeMap<BankKey, Arc<Fork>>>,
+}
+
+/// The latest votes of the primary nodes across all banks, updated from each
+/// added and pruned bank instead of rescanning `fork_map` after every block.
+#[derive(Default)]
+struct ForkChoice {
+    //for each node the number of banks with each of its latest votes, the highest one counts
+    seen: HashMap<ID, BTreeMap<BankKey, usize>>,
+    //total stake of the nodes whose latest vote is the bank
+    slot_votes: HashMap<BankKey, Stake>,
+    //stake that moved off and onto each bank since the fork weights were updated
+    removed: HashMap<BankKey, Stake>,
+    added: HashMap<BankKey, Stake>,
+}
+
+impl ForkChoice {
+    //count the `latest` votes of a bank, or stop counting them when it's pruned
+    fn update(&mut self, latest: &[(ID, BankKey)], pruned: bool, stakes: &Stakes) {
+        for (p, slot) in latest {
+            let seen = self.seen.entry(*p).or_default();
+            let before = seen.keys().next_back().cloned();
+            let count = seen.entry(*slot).or_insert(0);
+            if pruned {
+                *count -= 1;
+                if *count == 0 {
+                    seen.remove(slot);
+                }
+            } else {
+                *count += 1;
+            }
+            let after = seen.keys().next_back().cloned();
+            if seen.is_empty() {
+                self.seen.remove(p);
+            }
+            if before == after {
+                continue;
+            }
+            let stake = stakes.get(*p);
+            if let Some(old) = before {
+                let votes = self.slot_votes.get_mut(&old).unwrap();
+                *votes -= stake;
+                if *votes == 0 {
+                    self.slot_votes.remove(&old);
+                }
+                *self.removed.entry(old).or_insert(0) += stake;
+            }
+            if let Some(new) = after {
+                *self.slot_votes.entry(new).or_insert(0) += stake;
+                *self.added.entry(new).or_insert(0) += stake;
+            }
+        }
+    }
+}
+
+/// what `Forks::apply` did with a block
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Applied {
+    //a new bank, which may be another version of a slot that already has one
+    New,
+    //this version of the slot already has a bank
+    Known,
+    //the parent was pruned, only possible for another version of a slot
+    Pruned,
 }
 
 impl Default for Forks {
     fn default() -> Self {
-        let bank_zero = Bank::zero();
+        Self::new(Arc::new(SimConfig::default()))
+    }
+}
+
+impl Forks {
+    pub fn new(config: Arc<SimConfig>) -> Self {
+        let bank_zero = Bank::zero(config.clone());
         let mut fork_map = HashMap::new();
-        fork_map.insert(0, bank_zero);
-        let mut roots = HashSet::new();
-        roots.insert(0);
-        Self {
+        fork_map.insert(bank_zero.key(), bank_zero);
+        let roots = HashMap::from([(0, 0)]);
+        let mut forks = Self {
             roots,
             fork_map,
             primary_fork_weights: HashMap::new(),
-            lowest_root: Vote::zero(),
-        }
+            lowest_root: Vote::zero(config.depth),
+            detector: Detector::new(&config),
+            config,
+            events: vec![],
+            fork_choice: ForkChoice::default(),
+            ancestry: Ancestry::new((0, 0), (0, 0)),
+            paths: Mutex::default(),
+        };
+        forks.init_fork_choice();
+        forks
     }
-}
 
-impl Forks {
-    pub fn apply(&mut self, block: &Block) {
-        assert!(self.fork_map.get(&block.slot).is_none());
-        let parent = self.fork_map.get_mut(&block.parent).unwrap();
-        let parent_phase = parent.subcom.phase();
+    pub fn apply(&mut self, block: &Block) -> Result<Applied, SafetyViolation> {
+        let id = block.id();
+        if self.fork_map.contains_key(&(block.slot, id)) {
+            return Ok(Applied::Known);
+        }
+        //another version of the slot is applied next to the ones before it, and the
+        //producer is caught equivocating
+        let first = self
+            .fork_map
+            .values()
+            .filter(|b| b.slot == block.slot)
+            .min_by_key(|b| b.id);
+        if let Some(first) = first {
+            self.events.push(Event::DuplicateBlock {
+                slot: block.slot,
+                producer: block.producer,
+                id,
+                first_id: first.id,
+                parent: block.parent,
+                first_parent: first.parent,
+            });
+            self.detector
+                .equivocation(block.slot, block.producer, first.id, id);
+        }
+        let Some(parent) = self.fork_map.get(&(block.parent, block.parent_id)) else {
+            //another version of the slot rooted past the parent of this one
+            for evidence in std::mem::take(&mut self.detector.evidence) {
+                self.events.push(Event::Slashable { evidence });
+            }
+            return Ok(Applied::Pruned);
+        };
+        //nothing changes until every check passed, so a rejected block leaves the forks as they were
+        let parent_phase = parent.subcom.phase(&self.config);
         let mut bank = parent.child(block.slot);
-        let mut fork: HashSet<_> = self.compute_fork(block.parent).into_iter().collect();
-        fork.insert(bank.slot);
-        bank.apply(block, &fork);
+        let rotated = bank
+            .subcom
+            .rotation(&parent.subcom, &self.config)
+            .map(|phase| {
+                let members = match phase {
+                    Phase::FlipPrimary => &bank.subcom.primary,
+                    Phase::SwapSecondary => &bank.subcom.secondary,
+                };
+                let mut members: Vec<_> = members.iter().cloned().collect();
+                members.sort_unstable();
+                Event::SubcommitteeRotated {
+                    slot: bank.slot,
+                    phase,
+                    members,
+                }
+            });
+        let rv = bank.apply(block, &self.ancestry, &mut self.detector);
+        for evidence in std::mem::take(&mut self.detector.evidence) {
+            self.events.push(Event::Slashable { evidence });
+        }
+        rv?;
 
-        if Phase::FlipPrimary == bank.subcom.phase() && parent_phase != Phase::FlipPrimary {
+        if Phase::FlipPrimary == bank.subcom.phase(&self.config)
+            && parent_phase != Phase::FlipPrimary
+        {
             let primary = bank.primary_super_root().slot;
             let secondary = bank.secondary_super_root().slot;
-            let s = self.compute_fork(secondary);
-            let p = self.compute_fork(primary);
+            let key = |slot: Slot| self.version(bank.parent_key(), slot).map(|v| (slot, v));
+            let diverged = |slot: Slot| SafetyViolation::Diverged {
+                slot: bank.slot,
+                lowest_root: self.lowest_root.slot,
+                primary,
+                secondary,
+                fork: sorted(self.compute_fork(key(slot).unwrap_or((slot, 0))).keys()),
+            };
             if secondary >= self.lowest_root.slot && primary >= self.lowest_root.slot {
-                if secondary > primary {
-                    assert!(
-                        s.contains(&primary),
-                        "{} diverged {:?} {}",
-                        self.lowest_root.slot,
-                        s,
-                        primary
-                    );
-                }
-                if secondary < primary {
-                    assert!(
-                        p.contains(&secondary),
-                        "{} diverged {:?} {}",
-                        self.lowest_root.slot,
-                        p,
-                        secondary
-                    );
+                //the older super root has to be where the two forks meet
+                let lca = key(primary)
+                    .zip(key(secondary))
+                    .and_then(|(p, s)| self.ancestry.lca(p, s));
+                if primary != secondary && lca.map(|k| k.0) != Some(primary.min(secondary)) {
+                    return Err(diverged(primary.max(secondary)));
                 }
             } else {
-                if secondary < self.lowest_root.slot {
-                    assert!(self.roots.contains(&secondary));
-                }
-                if primary < self.lowest_root.slot {
-                    assert!(self.roots.contains(&primary));
+                for super_root in [secondary, primary] {
+                    if super_root < self.lowest_root.slot && !self.roots.contains_key(&super_root) {
+                        return Err(SafetyViolation::UnrootedSuperRoot {
+                            slot: bank.slot,
+                            lowest_root: self.lowest_root.slot,
+                            super_root,
+                        });
+                    }
                 }
             }
         }
 
+        //the new root is an ancestor of the bank, so it is checked before the bank is added
         let lowest_root = bank.lowest_primary_root();
-        assert!(self.fork_map.get(&bank.slot).is_none());
+        let rooted = lowest_root.slot > self.lowest_root.slot;
+        let new_root = self
+            .version(bank.parent_key(), lowest_root.slot)
+            .map(|v| (lowest_root.slot, v));
+        if rooted
+            && !new_root.is_some_and(|new_root| self.ancestry.is_ancestor(self.root(), new_root))
+        {
+            return Err(SafetyViolation::RootNotDescendant {
+                slot: block.slot,
+                old_root: self.lowest_root.slot,
+                new_root: lowest_root.slot,
+                fork: sorted(self.compute_fork((lowest_root.slot, 0)).keys()),
+            });
+        }
+
+        self.events.extend(rotated);
+        self.events.push(Event::BlockApplied {
+            slot: block.slot,
+            parent: block.parent,
+            producer: block.producer,
+            votes: block.votes.len(),
+        });
+        if bank.subcom.num_super_roots != bank.subcom.parent_num_super_roots {
+            self.events.push(Event::SuperRootAdvanced {
+                slot: bank.slot,
+                super_root: bank.subcom.super_root,
+            });
+        }
+        let key = bank.key();
+        assert!(!self.fork_map.contains_key(&key));
         let mut max_root = 0;
         for n in bank.nodes.iter() {
             if n.root.slot > max_root {
                 max_root = n.root.slot;
             }
         }
-        self.fork_map.insert(bank.slot, bank);
-        if lowest_root.slot > self.lowest_root.slot {
-            let new_roots = self.compute_fork(lowest_root.slot);
-            assert!(new_roots.contains(&self.lowest_root.slot));
-            self.roots.extend(&new_roots);
-
-            println!("ROOT DISTANCE {}", max_root - lowest_root.slot);
-            println!(
-                "LOWEST ROOT UPDATE {:?} {:?} MAX: {}",
-                self.lowest_root, lowest_root, max_root
-            );
+        self.fork_map
+            .get_mut(&bank.parent_key())
+            .unwrap()
+            .children
+            .push(key);
+        self.ancestry.insert(key, bank.parent_key());
+        self.fork_map.insert(key, bank);
+        let latest = self.latest_votes(&self.fork_map[&key]);
+        self.fork_choice.update(&latest, false, &self.config.stakes);
+        if let Some(new_root) = new_root.filter(|_| rooted) {
+            let new_roots = self.compute_fork(new_root);
+            let mut rooted: Vec<_> = new_roots
+                .keys()
+                .filter(|s| !self.roots.contains_key(s))
+                .cloned()
+                .collect();
+            rooted.sort_unstable();
+            self.roots.extend(new_roots.iter());
+
+            self.events.push(Event::RootAdvanced {
+                slot: block.slot,
+                from: self.lowest_root.slot,
+                to: lowest_root.slot,
+                max_root,
+                rooted,
+            });
             self.lowest_root = lowest_root;
+            self.detector.gc(lowest_root.slot);
             self.gc();
         }
-        self.build_fork_weights();
+        self.update_fork_weights(key, rooted);
+        Ok(Applied::New)
+    }
+
+    /// the fork weights are rebuilt on decode, pending events are not saved
+    pub fn encode(&self, w: &mut Writer) {
+        let mut keys: Vec<_> = self.fork_map.keys().cloned().collect();
+        keys.sort_unstable();
+        w.usize(keys.len());
+        for k in keys {
+            self.fork_map[&k].encode(w);
+        }
+        w.vote(&self.lowest_root);
+        w.map(&self.roots);
+        self.detector.encode(w);
+    }
+
+    pub fn decode(r: &mut Reader, config: Arc<SimConfig>) -> Result<Self, SnapshotError> {
+        let mut fork_map = HashMap::new();
+        for _ in 0..r.count()? {
+            let bank = Bank::decode(r, &config)?;
+            fork_map.insert(bank.key(), bank);
+        }
+        let lowest_root = r.vote()?;
+        let roots = r.map()?;
+        let detector = Detector::decode(r, &config)?;
+        let root = match roots.get(&lowest_root.slot) {
+            Some(id) => (lowest_root.slot, *id),
+            None => return corrupt("the lowest root isn't rooted"),
+        };
+        let linked = fork_map.values().all(|b| {
+            b.key() == root
+                || fork_map.contains_key(&b.parent_key())
+                    && b.children.iter().all(|c| fork_map.contains_key(c))
+        });
+        if !fork_map.contains_key(&root) || !linked {
+            return corrupt("banks are not connected to the lowest root");
+        }
+        let ancestry = Ancestry::new(root, fork_map[&root].parent_key());
+        let mut forks = Forks {
+            fork_map,
+            primary_fork_weights: HashMap::new(),
+            lowest_root,
+            roots,
+            detector,
+            config,
+            events: vec![],
+            fork_choice: ForkChoice::default(),
+            ancestry,
+            paths: Mutex::default(),
+        };
+        let mut keys: Vec<_> = forks.fork_map.keys().cloned().collect();
+        keys.sort_unstable();
+        for k in keys {
+            let parent = forks.fork_map[&k].parent_key();
+            if k == root {
+                continue;
+            }
+            if !forks.ancestry.contains(parent) {
+                return corrupt(format!("bank {} doesn't descend from the lowest root", k.0));
+            }
+            forks.ancestry.insert(k, parent);
+        }
+        forks.init_fork_choice();
+        Ok(forks)
     }
 
     pub fn latest_primary(&self) -> HashSet<ID> {
@@ -107,67 +365,198 @@ impl Forks {
             .clone()
     }
 
-    pub fn compute_fork(&self, slot: Slot) -> HashSet<Slot> {
-        let mut fork = vec![slot];
+    /// the bank of a slot with a single version
+    #[cfg(test)]
+    pub fn key(&self, slot: Slot) -> BankKey {
+        let mut keys = self.fork_map.keys().filter(|k| k.0 == slot);
+        let key = *keys.next().unwrap();
+        assert!(
+            keys.next().is_none(),
+            "slot {} has more than one version",
+            slot
+        );
+        key
+    }
+
+    /// the bank of the lowest root
+    pub fn root(&self) -> BankKey {
+        (self.lowest_root.slot, self.roots[&self.lowest_root.slot])
+    }
+
+    /// The version of `slot` in the fork of `key`, below the lowest root that is
+    /// the rooted one. `None` if the fork doesn't have the slot.
+    pub fn version(&self, key: BankKey, slot: Slot) -> Option<u64> {
+        match self.ancestry.version(key, slot) {
+            Some(id) => Some(id),
+            None if slot < self.ancestry.base().0 => self.roots.get(&slot).cloned(),
+            None => None,
+        }
+    }
+
+    //the latest vote of each primary node in `bank` and the bank it is for
+    fn latest_votes(&self, bank: &Bank) -> Vec<(ID, BankKey)> {
+        bank.primary_latest_votes()
+            .into_iter()
+            .map(|(id, slot)| {
+                let version = self
+                    .version(bank.key(), slot)
+                    .expect("a vote in a bank is in its fork");
+                (id, (slot, version))
+            })
+            .collect()
+    }
+
+    /// The slots from `key` down to the parent of the lowest root. Forks are
+    /// cached until the next gc, use `ancestry` to only check one slot.
+    pub fn compute_fork(&self, key: BankKey) -> Arc<Fork> {
+        if !self.fork_map.contains_key(&key) {
+            return Arc::new(HashMap::from([key]));
+        }
+        if let Some(fork) = self.paths.lock().unwrap().get(&key) {
+            return fork.clone();
+        }
+        let mut fork = vec![key];
         loop {
             let last = fork.last().unwrap();
             if let Some(b) = self.fork_map.get(last) {
-                if *last == b.parent {
+                if *last == b.parent_key() {
                     break;
                 }
-                fork.push(b.parent)
+                fork.push(b.parent_key())
             } else {
                 break;
             }
         }
-        fork.into_iter().collect()
+        let fork = Arc::new(fork.into_iter().collect::<Fork>());
+        let mut paths = self.paths.lock().unwrap();
+        paths.insert(key, fork.clone());
+        if paths.len() > MAX_PATHS {
+            paths.pop_first();
+        }
+        fork
     }
 
     //only keep forks that are connected to root
     fn gc(&mut self) {
         let mut valid = vec![];
 
-        println!("START GC {:?}", self.lowest_root);
-        let mut children = vec![self.lowest_root.slot];
-        while !children.is_empty() {
-            let slot = children.pop().unwrap();
-            valid.push(slot);
-            let bank = self.fork_map.get(&slot).unwrap();
+        let mut children = vec![self.root()];
+        while let Some(key) = children.pop() {
+            valid.push(key);
+            let bank = self.fork_map.get(&key).unwrap();
             children.extend_from_slice(&bank.children);
         }
         let mut new_banks = HashMap::new();
         for v in valid {
             new_banks.insert(v, self.fork_map.remove(&v).unwrap());
         }
+        let pruned: Vec<_> = self
+            .fork_map
+            .values()
+            .map(|bank| self.latest_votes(bank))
+            .collect();
+        for latest in pruned {
+            self.fork_choice.update(&latest, true, &self.config.stakes);
+        }
+        let base = new_banks[&self.root()].parent_key();
+        self.ancestry.retain(|k| new_banks.contains_key(k), base);
+        self.paths.lock().unwrap().clear();
         //self.roots.retain(|x| x + 1000 > self.lowest_root.slot);
+        self.events.push(Event::Gc {
+            root: self.lowest_root.slot,
+            kept: new_banks.len(),
+            pruned: self.fork_map.len(),
+            orphaned: self
+                .fork_map
+                .keys()
+                .filter(|k| self.roots.get(&k.0) != Some(&k.1))
+                .count(),
+        });
         self.fork_map = new_banks;
     }
     /// A validator V's vote on an ancestor X counts towards a descendant
     /// Y even if the validator is not locked out on X at Y anymore,
     /// as long as X is the latest vote observed from this validator V
+    ///
+    /// Recomputes the weights from every bank, `apply` keeps them up to date
+    /// incrementally with the same result.
     pub fn build_fork_weights(&mut self) {
         //each validators latest votes
-        let mut primary_latest_votes: HashMap<ID, Slot> = HashMap::new();
-        for v in self.fork_map.values() {
-            v.primary_latest_votes(&mut primary_latest_votes);
-        }
-        //total stake voting per slot
-        let mut slot_votes: HashMap<Slot, usize> = HashMap::new();
-        for (_, v) in &primary_latest_votes {
-            let e = slot_votes.entry(*v).or_insert(0);
-            *e = *e + 1;
-        }
-        //stake weight is inherited from the parent
-        let mut weights: HashMap<Slot, usize> = HashMap::new();
-        let mut children = vec![self.lowest_root.slot];
-        while !children.is_empty() {
-            let child = children.pop().unwrap();
+        let mut primary_latest_votes: HashMap<ID, BankKey> = HashMap::new();
+        for bank in self.fork_map.values() {
+            for (id, key) in self.latest_votes(bank) {
+                let e = primary_latest_votes.entry(id).or_insert(key);
+                if *e < key {
+                    *e = key;
+                }
+            }
+        }
+        //total stake voting per bank
+        let mut slot_votes: HashMap<BankKey, Stake> = HashMap::new();
+        for (id, v) in &primary_latest_votes {
+            *slot_votes.entry(*v).or_insert(0) += self.config.stakes.get(*id);
+        }
+        self.primary_fork_weights = self.path_weights(&slot_votes);
+    }
+
+    //stake weight is inherited from the parent
+    fn path_weights(&self, slot_votes: &HashMap<BankKey, Stake>) -> HashMap<BankKey, Stake> {
+        let mut weights: HashMap<BankKey, Stake> = HashMap::new();
+        let mut children = vec![self.root()];
+        while let Some(child) = children.pop() {
             let bank = self.fork_map.get(&child).unwrap();
             children.extend_from_slice(&bank.children);
-            let parent_weight = *weights.get(&bank.parent).unwrap_or(&0);
-            let e = weights.entry(child).or_insert(parent_weight);
-            *e = *e + *slot_votes.get(&child).unwrap_or(&0);
+            let parent_weight = *weights.get(&bank.parent_key()).unwrap_or(&0);
+            *weights.entry(child).or_insert(parent_weight) += *slot_votes.get(&child).unwrap_or(&0);
+        }
+        weights
+    }
+
+    fn init_fork_choice(&mut self) {
+        let mut fork_choice = ForkChoice::default();
+        for bank in self.fork_map.values() {
+            fork_choice.update(&self.latest_votes(bank), false, &self.config.stakes);
+        }
+        self.fork_choice = fork_choice;
+        self.update_fork_weights(self.root(), true);
+    }
+
+    //bring the weights up to date after the bank `key` was added, the moved
+    //stake only changes the weights of the forks below the banks it moved between
+    fn update_fork_weights(&mut self, key: BankKey, rooted: bool) {
+        let removed = std::mem::take(&mut self.fork_choice.removed);
+        let added = std::mem::take(&mut self.fork_choice.added);
+        if rooted {
+            //the banks below the new root don't count anymore
+            self.primary_fork_weights = self.path_weights(&self.fork_choice.slot_votes);
+            return;
+        }
+        //the weight of the new bank before the stake moved
+        let votes = self.fork_choice.slot_votes.get(&key).unwrap_or(&0)
+            + removed.get(&key).unwrap_or(&0)
+            - added.get(&key).unwrap_or(&0);
+        let parent = self.fork_map[&key].parent_key();
+        let weight = self.primary_fork_weights[&parent] + votes;
+        self.primary_fork_weights.insert(key, weight);
+        for (moved, stake, add) in removed
+            .into_iter()
+            .map(|(s, w)| (s, w, false))
+            .chain(added.into_iter().map(|(s, w)| (s, w, true)))
+        {
+            if !self.primary_fork_weights.contains_key(&moved) {
+                //votes below the lowest root don't count
+                continue;
+            }
+            let mut children = vec![moved];
+            while let Some(child) = children.pop() {
+                children.extend_from_slice(&self.fork_map[&child].children);
+                let weight = self.primary_fork_weights.get_mut(&child).unwrap();
+                if add {
+                    *weight += stake;
+                } else {
+                    *weight -= stake;
+                }
+            }
         }
-        self.primary_fork_weights = weights;
     }
 }
@@ -0,0 +1,364 @@
+//! Random partition schedules with safety and liveness checks.
+//!
+//! A schedule is generated from a seed and rendered as a scenario file, see
+//! `scenario`. It splits the nodes into random partitions, runs some of them
+//! with a chosen leader, repairs them, and can make a minority of the nodes
+//! byzantine. Every schedule ends by repairing all partitions and stepping
+//! every node, the lowest root must advance then. A failing schedule is shrunk
+//! to the smallest one that still fails the same way, its scenario file
+//! replays the failure with `tower_sim scenario PATH`.
+use crate::bank::ID;
+use crate::config::SimConfig;
+use crate::scenario::{Scenario, ScenarioError};
+use crate::seed::{self, Stream};
+use crate::voter::VoterKind;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Op {
+    //`Network::step` with `partitions` even splits
+    Step {
+        slots: usize,
+        partitions: usize,
+    },
+    //only the `active` partitions are live and `producer` makes every block
+    Run {
+        slots: usize,
+        active: Vec<usize>,
+        producer: ID,
+    },
+    Repair {
+        active: Vec<usize>,
+    },
+}
+
+/// a minority of the nodes that don't follow the protocol
+#[derive(Clone, Debug, PartialEq)]
+pub enum Byzantine {
+    Voter(VoterKind),
+    Equivocate,
+    MinorityFork,
+    Censor((ID, ID)),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Schedule {
+    //master seed of the run
+    pub seed: u64,
+    pub nodes: usize,
+    pub depth: usize,
+    pub subcommittee_size: usize,
+    pub leader_window: usize,
+    //the members of each partition, every node is in exactly one
+    pub partitions: Vec<Vec<ID>>,
+    pub byzantine: Vec<((ID, ID), Byzantine)>,
+    pub ops: Vec<Op>,
+    //slots every node runs after the final repair
+    pub heal: usize,
+}
+
+impl Schedule {
+    /// a random schedule, the same seed generates the same schedule
+    pub fn generate(seed: u64) -> Self {
+        let mut rng = seed::rng(seed, Stream::Fuzz, 0);
+        let nodes = rng.gen_range(12..=32);
+        let depth = 8;
+        let num_partitions = rng.gen_range(2..=4);
+        let mut ids: Vec<ID> = (0..nodes).collect();
+        ids.shuffle(&mut rng);
+        let mut partitions = vec![vec![]; num_partitions];
+        for (ix, id) in ids.into_iter().enumerate() {
+            //the first nodes make sure that no partition is empty
+            let p = if ix < num_partitions {
+                ix
+            } else {
+                rng.gen_range(0..num_partitions)
+            };
+            partitions[p].push(id);
+        }
+        for p in &mut partitions {
+            p.sort_unstable();
+        }
+        //less than a third of the nodes are byzantine
+        let mut byzantine = vec![];
+        let mut start = 0;
+        while rng.gen_bool(0.5) {
+            let end = start + rng.gen_range(1..=3);
+            if end * 3 >= nodes {
+                break;
+            }
+            let kind = match rng.gen_range(0..6) {
+                0 => Byzantine::Voter(VoterKind::LockoutViolating),
+                1 => Byzantine::Voter(VoterKind::ThresholdIgnoring),
+                2 => Byzantine::Voter(VoterKind::SwitchingProofForging),
+                3 => Byzantine::Equivocate,
+                4 => Byzantine::MinorityFork,
+                _ => {
+                    let censored = rng.gen_range(0..nodes);
+                    Byzantine::Censor((censored, censored + 1))
+                }
+            };
+            byzantine.push(((start, end), kind));
+            start = end;
+        }
+        let mut ops = vec![];
+        for _ in 0..rng.gen_range(1..=12) {
+            let slots = rng.gen_range(1..=64);
+            let mut active: Vec<usize> =
+                (0..num_partitions).filter(|_| rng.gen_bool(0.5)).collect();
+            if active.is_empty() {
+                active.push(rng.gen_range(0..num_partitions));
+            }
+            let op = match rng.gen_range(0..3) {
+                0 => Op::Step {
+                    slots,
+                    partitions: rng.gen_range(1..=4),
+                },
+                1 => {
+                    let p = active[rng.gen_range(0..active.len())];
+                    let producer = partitions[p][rng.gen_range(0..partitions[p].len())];
+                    Op::Run {
+                        slots,
+                        active,
+                        producer,
+                    }
+                }
+                _ => Op::Repair { active },
+            };
+            ops.push(op);
+        }
+        Schedule {
+            seed,
+            nodes,
+            depth,
+            subcommittee_size: rng.gen_range(nodes / 2..=nodes),
+            leader_window: rng.gen_range(1..=4),
+            partitions,
+            byzantine,
+            ops,
+            //long enough for the longest lockout to expire
+            heal: (1 << depth) + 64,
+        }
+    }
+
+    pub fn scenario(&self) -> Scenario {
+        Scenario::parse(&self.to_string(), SimConfig::default())
+            .expect("a generated schedule is a valid scenario")
+    }
+
+    /// run the schedule, panics in the simulator are failures too
+    pub fn check(&self) -> Result<(), Failure> {
+        let scenario = self.scenario();
+        let rv = panic::catch_unwind(AssertUnwindSafe(|| {
+            let mut network = scenario.network();
+            scenario.run(&mut network)
+        }));
+        match rv {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(e @ ScenarioError::Safety { .. })) => Err(Failure::Safety(e.to_string())),
+            Ok(Err(e @ ScenarioError::Failed { .. })) => Err(Failure::Liveness(e.to_string())),
+            Ok(Err(e)) => panic!("invalid generated schedule: {}", e),
+            Err(e) => {
+                let msg = match (e.downcast_ref::<&str>(), e.downcast_ref::<String>()) {
+                    (Some(msg), _) => msg.to_string(),
+                    (_, Some(msg)) => msg.clone(),
+                    _ => "unknown panic".to_string(),
+                };
+                Err(Failure::Panic(msg))
+            }
+        }
+    }
+
+    /// Smaller versions of the schedule, with fewer ops or byzantine nodes and
+    /// with shorter ops. Only the first few slots of an op are kept.
+    fn candidates(&self) -> Vec<Schedule> {
+        let mut candidates = vec![];
+        let mut size = self.ops.len();
+        while size > 0 {
+            for start in (0..self.ops.len()).step_by(size) {
+                let mut s = self.clone();
+                s.ops.drain(start..(start + size).min(s.ops.len()));
+                candidates.push(s);
+            }
+            size /= 2;
+        }
+        for ix in 0..self.byzantine.len() {
+            let mut s = self.clone();
+            s.byzantine.remove(ix);
+            candidates.push(s);
+        }
+        for ix in 0..self.ops.len() {
+            let slots = match &self.ops[ix] {
+                Op::Step { slots, .. } | Op::Run { slots, .. } => *slots,
+                Op::Repair { .. } => continue,
+            };
+            for shorter in [1, slots / 2, slots - 1] {
+                if shorter == 0 || shorter >= slots {
+                    continue;
+                }
+                let mut s = self.clone();
+                if let Op::Step { slots, .. } | Op::Run { slots, .. } = &mut s.ops[ix] {
+                    *slots = shorter;
+                }
+                candidates.push(s);
+            }
+        }
+        candidates
+    }
+}
+
+/// Greedily replace `schedule` with the first smaller candidate that still
+/// `fails`, until none of them does.
+pub fn shrink(schedule: &Schedule, mut fails: impl FnMut(&Schedule) -> bool) -> Schedule {
+    let mut best = schedule.clone();
+    while let Some(smaller) = best.candidates().into_iter().find(|s| fails(s)) {
+        best = smaller;
+    }
+    best
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Failure {
+    Safety(String),
+    //the lowest root didn't advance after the final repair
+    Liveness(String),
+    Panic(String),
+}
+
+impl Failure {
+    fn kind(&self) -> &'static str {
+        match self {
+            Failure::Safety(_) => "safety",
+            Failure::Liveness(_) => "liveness",
+            Failure::Panic(_) => "panic",
+        }
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Failure::Safety(msg) | Failure::Liveness(msg) | Failure::Panic(msg) => {
+                write!(f, "{} failure: {}", self.kind(), msg)
+            }
+        }
+    }
+}
+
+/// Check the schedules of `seeds` in order. The first failure is shrunk while
+/// it fails the same way, and returned with the failure of the shrunk schedule.
+pub fn fuzz(seeds: impl IntoIterator<Item = u64>) -> Option<(Schedule, Failure)> {
+    for seed in seeds {
+        let schedule = Schedule::generate(seed);
+        if let Err(failure) = schedule.check() {
+            let shrunk = shrink(&schedule, |s| {
+                s.check().is_err_and(|f| f.kind() == failure.kind())
+            });
+            let failure = shrunk.check().unwrap_err();
+            return Some((shrunk, failure));
+        }
+    }
+    None
+}
+
+/// the schedule as a scenario file
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "nodes {}", self.nodes)?;
+        writeln!(f, "seed {}", self.seed)?;
+        writeln!(f, "depth {}", self.depth)?;
+        writeln!(f, "threshold {}", self.depth / 2)?;
+        writeln!(f, "subcommittee_size {}", self.subcommittee_size)?;
+        writeln!(f, "leader_window {}", self.leader_window)?;
+        for (ix, members) in self.partitions.iter().enumerate() {
+            let members: Vec<_> = members.iter().map(|id| id.to_string()).collect();
+            writeln!(f, "partition p{} {}", ix, members.join(","))?;
+        }
+        for ((start, end), kind) in &self.byzantine {
+            match kind {
+                Byzantine::Voter(kind) => writeln!(f, "voter {} {}..{}", kind.name(), start, end)?,
+                Byzantine::Equivocate => writeln!(f, "strategy equivocate {}..{}", start, end)?,
+                Byzantine::MinorityFork => {
+                    writeln!(f, "strategy minority_fork {}..{}", start, end)?
+                }
+                Byzantine::Censor((s, e)) => {
+                    writeln!(f, "strategy censor {}..{} censor={}..{}", start, end, s, e)?
+                }
+            }
+        }
+        let names = |active: &[usize]| {
+            let names: Vec<_> = active.iter().map(|p| format!("p{}", p)).collect();
+            names.join(",")
+        };
+        for op in &self.ops {
+            match op {
+                Op::Step { slots, partitions } => {
+                    writeln!(f, "step {} partitions={}", slots, partitions)?
+                }
+                Op::Run {
+                    slots,
+                    active,
+                    producer,
+                } => writeln!(
+                    f,
+                    "run {} active={} producer={}",
+                    slots,
+                    names(active),
+                    producer
+                )?,
+                Op::Repair { active } => writeln!(f, "repair {}", names(active))?,
+            }
+        }
+        let all: Vec<usize> = (0..self.partitions.len()).collect();
+        writeln!(f, "repair {}", names(&all))?;
+        writeln!(f, "mark")?;
+        writeln!(f, "step {}", self.heal)?;
+        writeln!(f, "expect progress >= 1")
+    }
+}
+
+#[test]
+fn test_generated_schedules_pass() {
+    assert_eq!(Schedule::generate(3), Schedule::generate(3));
+    assert_ne!(Schedule::generate(3), Schedule::generate(4));
+    assert_eq!(fuzz(0..4), None);
+}
+
+#[test]
+fn test_shrink_to_minimal_schedule() {
+    let mut schedule = Schedule::generate(0);
+    schedule.byzantine = vec![((0, 1), Byzantine::Equivocate)];
+    schedule.ops = vec![
+        Op::Step {
+            slots: 16,
+            partitions: 2,
+        },
+        Op::Run {
+            slots: 40,
+            active: vec![0],
+            producer: schedule.partitions[0][0],
+        },
+        Op::Repair { active: vec![0, 1] },
+        Op::Step {
+            slots: 7,
+            partitions: 1,
+        },
+    ];
+    //stands in for a bug that needs a long enough run of one partition
+    let fails = |s: &Schedule| {
+        s.ops
+            .iter()
+            .any(|op| matches!(op, Op::Run { slots, .. } if *slots >= 10))
+    };
+    let shrunk = shrink(&schedule, fails);
+    assert!(shrunk.byzantine.is_empty());
+    assert_eq!(shrunk.ops.len(), 1);
+    assert!(matches!(shrunk.ops[0], Op::Run { slots: 10, .. }));
+    //the shrunk schedule is still a runnable scenario
+    let scenario = shrunk.scenario();
+    assert_eq!(scenario.config.num_nodes(), shrunk.nodes);
+    assert_eq!(scenario.commands.len(), 5);
+}
@@ -0,0 +1,170 @@
+use crate::bank::ID;
+use crate::connectivity::Nodes;
+use crate::seed::{self, Stream};
+use crate::snapshot::{corrupt, Reader, SnapshotError, Writer};
+use crate::tower::Slot;
+use std::fmt;
+
+/// How many slots a message takes to arrive.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Delay {
+    Fixed(Slot),
+    //between min and max slots, both included
+    Uniform { min: Slot, max: Slot },
+}
+
+impl Default for Delay {
+    fn default() -> Self {
+        Delay::Fixed(0)
+    }
+}
+
+impl Delay {
+    /// `N` or `MIN..=MAX`
+    pub fn parse(val: &str) -> Option<Self> {
+        match val.split_once("..=") {
+            Some((min, max)) => {
+                let (min, max) = (min.parse().ok()?, max.parse().ok()?);
+                (min <= max).then_some(Delay::Uniform { min, max })
+            }
+            None => val.parse().ok().map(Delay::Fixed),
+        }
+    }
+
+    fn sample(&self, draw: u64) -> Slot {
+        match *self {
+            Delay::Fixed(delay) => delay,
+            //`0..=u64::MAX` has one more value than a u64 can count, every draw is in it
+            Delay::Uniform { min, max } => match (max - min).checked_add(1) {
+                Some(range) => min + draw % range,
+                None => draw,
+            },
+        }
+    }
+
+    fn encode(&self, w: &mut Writer) {
+        match *self {
+            Delay::Fixed(delay) => {
+                w.u64(0);
+                w.u64(delay);
+            }
+            Delay::Uniform { min, max } => {
+                w.u64(1);
+                w.u64(min);
+                w.u64(max);
+            }
+        }
+    }
+
+    fn decode(r: &mut Reader) -> Result<Self, SnapshotError> {
+        match r.u64()? {
+            0 => Ok(Delay::Fixed(r.u64()?)),
+            1 => {
+                let (min, max) = (r.u64()?, r.u64()?);
+                if min > max {
+                    return corrupt(format!("invalid delay {}..={}", min, max));
+                }
+                Ok(Delay::Uniform { min, max })
+            }
+            tag => corrupt(format!("invalid delay tag {}", tag)),
+        }
+    }
+}
+
+impl fmt::Display for Delay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Delay::Fixed(delay) => write!(f, "{}", delay),
+            Delay::Uniform { min, max } => write!(f, "{}..={}", min, max),
+        }
+    }
+}
+
+/// Message delays between nodes, blocks are sent from the producer to every
+/// node and votes from the voter to the leader of the slot. Links from one node
+/// range to another override the default, later links win. A node reaches
+/// itself instantly.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Latency {
+    pub delay: Delay,
+    pub links: Vec<(Nodes, Nodes, Delay)>,
+}
+
+impl Latency {
+    /// every link has the same delay
+    pub fn uniform(delay: Delay) -> Self {
+        Latency {
+            delay,
+            links: vec![],
+        }
+    }
+
+    /// messages from `from` to `to` take `delay`
+    pub fn set(&mut self, from: Nodes, to: Nodes, delay: Delay) {
+        self.links.push((from, to, delay));
+    }
+
+    pub fn link(&self, from: ID, to: ID) -> Delay {
+        if from == to {
+            return Delay::Fixed(0);
+        }
+        self.links
+            .iter()
+            .rev()
+            .find(|((fs, fe), (ts, te), _)| (*fs..*fe).contains(&from) && (*ts..*te).contains(&to))
+            .map_or(self.delay, |(_, _, delay)| *delay)
+    }
+
+    /// Delay of a message sent from `from` to `to` in `slot`, derived from the
+    /// master seed. Messages on the same link in the same slot share the delay.
+    pub fn delay(&self, seed: u64, from: ID, to: ID, slot: Slot) -> Slot {
+        let link = self.link(from, to);
+        if let Delay::Fixed(delay) = link {
+            return delay;
+        }
+        let index = seed::hash(seed::hash(slot ^ seed::hash(from as u64)) ^ to as u64);
+        link.sample(seed::derive(seed, Stream::Latency, index))
+    }
+
+    pub fn encode(&self, w: &mut Writer) {
+        self.delay.encode(w);
+        w.usize(self.links.len());
+        for ((fs, fe), (ts, te), delay) in &self.links {
+            for id in [fs, fe, ts, te] {
+                w.usize(*id);
+            }
+            delay.encode(w);
+        }
+    }
+
+    pub fn decode(r: &mut Reader) -> Result<Self, SnapshotError> {
+        let mut latency = Latency::uniform(Delay::decode(r)?);
+        for _ in 0..r.count()? {
+            let from = (r.usize()?, r.usize()?);
+            let to = (r.usize()?, r.usize()?);
+            latency.set(from, to, Delay::decode(r)?);
+        }
+        Ok(latency)
+    }
+}
+
+#[test]
+fn test_link_delays() {
+    let mut latency = Latency::uniform(Delay::Uniform { min: 1, max: 3 });
+    latency.set((0, 4), (4, 8), Delay::Fixed(5));
+    assert_eq!(latency.delay(0, 0, 0, 1), 0);
+    assert_eq!(latency.delay(0, 1, 6, 1), 5);
+    assert_eq!(latency.delay(0, 6, 1, 7), latency.delay(0, 6, 1, 7));
+    let delays: Vec<_> = (0..1000).map(|s| latency.delay(0, 6, 1, s)).collect();
+    assert!(delays.iter().all(|d| (1..=3).contains(d)));
+    assert!((1..=3).all(|d| delays.contains(&d)));
+    assert_eq!(Delay::parse("2"), Some(Delay::Fixed(2)));
+    assert_eq!(
+        Delay::parse("1..=3"),
+        Some(Delay::Uniform { min: 1, max: 3 })
+    );
+    assert_eq!(Delay::parse("3..=1"), None);
+    let full = Delay::parse(&format!("0..={}", u64::MAX)).unwrap();
+    assert_eq!(full.sample(u64::MAX), u64::MAX);
+    assert_eq!(Delay::Uniform { min: 5, max: 5 }.sample(u64::MAX), 5);
+}
@@ -0,0 +1,97 @@
+use crate::bank::ID;
+use crate::config::SimConfig;
+use crate::seed::{self, Stream};
+use crate::stake::Stake;
+use crate::tower::Slot;
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::Rng;
+
+//defaults, see `SimConfig`
+pub const LEADER_WINDOW: usize = 4;
+pub const LEADER_EPOCH: usize = 8192;
+
+/// Block producers for every slot. Each epoch of `leader_epoch` slots gets a schedule
+/// of consecutive `leader_window` slot windows, one leader per window, sampled from
+/// the master seed and the epoch, by stake if `stake_weighted_leaders` is set.
+pub struct LeaderSchedule {
+    seed: u64,
+    window: usize,
+    epoch_slots: usize,
+    num_nodes: usize,
+    sampler: Option<WeightedIndex<Stake>>,
+    //the epoch of `leaders`
+    epoch: Option<u64>,
+    leaders: Vec<ID>,
+}
+
+impl LeaderSchedule {
+    pub fn new(config: &SimConfig) -> Self {
+        assert!(config.leader_window > 0, "empty leader window");
+        assert!(
+            config.leader_epoch >= config.leader_window,
+            "leader epoch is shorter than a window"
+        );
+        LeaderSchedule {
+            seed: config.seed,
+            window: config.leader_window,
+            epoch_slots: config.leader_epoch,
+            num_nodes: config.num_nodes(),
+            sampler: config
+                .stake_weighted_leaders
+                .then(|| config.stakes.sampler()),
+            epoch: None,
+            leaders: vec![],
+        }
+    }
+
+    pub fn epoch(&self, slot: Slot) -> u64 {
+        slot / self.epoch_slots as u64
+    }
+
+    /// the leader of every window in `epoch`, the last window is cut short
+    /// if the epoch isn't a multiple of the window length
+    pub fn schedule(&self, epoch: u64) -> Vec<ID> {
+        let mut rng = seed::rng(self.seed, Stream::Leader, epoch);
+        let windows = self.epoch_slots.div_ceil(self.window);
+        (0..windows)
+            .map(|_| match &self.sampler {
+                Some(sampler) => sampler.sample(&mut rng),
+                None => rng.gen_range(0..self.num_nodes),
+            })
+            .collect()
+    }
+
+    pub fn leader(&mut self, slot: Slot) -> ID {
+        let epoch = self.epoch(slot);
+        if self.epoch != Some(epoch) {
+            self.leaders = self.schedule(epoch);
+            self.epoch = Some(epoch);
+        }
+        let offset = (slot % self.epoch_slots as u64) as usize;
+        self.leaders[offset / self.window]
+    }
+}
+
+#[test]
+fn test_leader_windows() {
+    use crate::stake::Stakes;
+    let mut config = SimConfig::new(8);
+    config.leader_window = 4;
+    config.leader_epoch = 64;
+    config.stakes = Stakes::new(vec![1, 1, 1, 1, 0, 0, 0, 0]);
+    let mut schedule = LeaderSchedule::new(&config);
+    let leaders: Vec<_> = (0..256).map(|s| schedule.leader(s)).collect();
+    for window in leaders.chunks(4) {
+        assert!(window.iter().all(|l| *l == window[0]));
+    }
+    //nodes without stake never lead
+    assert!(leaders.iter().all(|l| *l < 4));
+    //epochs are sampled independently
+    assert_ne!(leaders[..64], leaders[64..128]);
+    let mut again = LeaderSchedule::new(&config);
+    assert_eq!(again.leader(130), leaders[130]);
+
+    config.stake_weighted_leaders = false;
+    let mut uniform = LeaderSchedule::new(&config);
+    assert!((0..256).any(|s| uniform.leader(s) >= 4));
+}
@@ -1,6 +1,24 @@
+pub mod ancestry;
+pub mod availability;
 pub mod bank;
+pub mod config;
+pub mod connectivity;
+pub mod events;
 pub mod forks;
+pub mod fuzz;
+pub mod latency;
+pub mod leader_schedule;
+pub mod mempool;
+pub mod metrics;
 pub mod network;
 pub mod node;
+pub mod producer;
+pub mod safety;
+pub mod scenario;
+pub mod seed;
+pub mod slashing;
+pub mod snapshot;
+pub mod stake;
 pub mod subcommittee;
 pub mod tower;
+pub mod voter;
@@ -1,22 +1,453 @@
-use rand::rngs::StdRng;
 use rand::Rng;
-use rand::SeedableRng;
+use std::fmt;
+use std::fs;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::process::exit;
+use tower_sim::availability::Availability;
 use tower_sim::bank::NUM_NODES;
-use tower_sim::network;
-use tower_sim::tower::DEPTH;
+use tower_sim::config::SimConfig;
+use tower_sim::connectivity::NodeSet;
+use tower_sim::events::JsonLinesSink;
+use tower_sim::fuzz;
+use tower_sim::latency::{Delay, Latency};
+use tower_sim::mempool::VotePolicy;
+use tower_sim::network::Network;
+use tower_sim::safety::SafetyViolation;
+use tower_sim::scenario::{Scenario as ScenarioFile, ScenarioError};
+use tower_sim::snapshot::SnapshotError;
+use tower_sim::stake::Stakes;
+use tower_sim::subcommittee::Subcommittee;
+use tower_sim::tower::Slot;
+use tower_sim::voter::VoterKind;
+
+const USAGE: &str = "usage: tower_sim [SCENARIO] [OPTIONS]
+
+scenarios:
+    four-partitions      split into 3 partitions and repair them one at a time (default)
+    partition-test-1     split the primary into 66/32/1/1 groups and build competing forks
+    random-partitions    random number of partitions with random repair times
+    scenario <PATH>      run the scenario file at PATH, --nodes and --stakes are the defaults
+    fuzz                 check --runs random partition schedules starting at --seed, the
+                         first failure is shrunk and written to --repro as a scenario file,
+                         every schedule picks its own nodes, leader window and partitions
+
+options:
+    --seed <N>           master seed of the run (default 0), a scenario file can override it
+    --slots <N>          number of slots to run, for partition-test-1 the length of each phase
+    --nodes <N>          number of nodes with uniform stake (default 1000)
+    --stakes <PATH>      load the stake table from PATH, one stake per line
+    --leader-window <N>  consecutive slots produced by each leader (default 4)
+    --skip-rate <P>      probability that a leader skips its slot (default 0)
+    --latency <D>        slots a block or vote takes between two nodes, N or MIN..=MAX (default 0)
+    --vote-policy <OPT,...>  how votes land in blocks: loss=P lost in transit, cap=N votes per
+                         block, recent to include the newest first, max_age=N to drop older
+                         votes, once to land each vote a single time
+    --byzantine <KIND=F,...>  nodes with fraction F of the stake vote with KIND: crash_silent,
+                         lockout_violating, threshold_ignoring or switching_proof_forging
+    --output <PATH>      write the run report to PATH instead of stdout
+    --events <PATH>      write the event log to PATH as json lines
+    --metrics <PATH>     export the metrics summary to PATH, csv if PATH ends in .csv else json
+    --runs <N>           number of fuzz schedules (default 100)
+    --repro <PATH>       where fuzz writes a failing schedule (default fuzz-repro.scenario)
+    --checkpoint <PATH>  save a snapshot of the run to PATH at the end, four-partitions and
+                         random-partitions only
+    --checkpoint-every <N>  also save the snapshot every N slots
+    --restore <PATH>     continue the run saved at PATH with the snapshot's config, so it
+                         can't be combined with --nodes, --stakes, --seed, --leader-window,
+                         --skip-rate, --latency or --vote-policy, --slots is the total
+                         including the restored slots
+    -h, --help           print this message";
+
+#[derive(Clone, Debug, PartialEq)]
+enum Scenario {
+    FourPartitions,
+    PartitionTest1,
+    RandomPartitions,
+    File(String),
+    Fuzz,
+}
+
+struct Args {
+    scenario: Scenario,
+    slots: Option<usize>,
+    config: SimConfig,
+    output: Option<String>,
+    events: Option<String>,
+    metrics: Option<String>,
+    checkpoint: Option<String>,
+    checkpoint_every: Option<u64>,
+    restore: Option<String>,
+    byzantine: Vec<(VoterKind, f64)>,
+    runs: u64,
+    repro: String,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Args, String> {
+    let mut scenario = None;
+    let mut seed = None;
+    let mut leader_window = None;
+    let mut skip_rate = None;
+    let mut latency = None;
+    let mut vote_policy = None;
+    let mut slots = None;
+    let mut nodes = None;
+    let mut stakes = None;
+    let mut output = None;
+    let mut events = None;
+    let mut metrics = None;
+    let mut checkpoint = None;
+    let mut checkpoint_every = None;
+    let mut restore = None;
+    let mut byzantine = vec![];
+    let mut runs = 100;
+    let mut repro = "fuzz-repro.scenario".to_string();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .ok_or_else(|| format!("missing value for {}", name))
+        };
+        match arg.as_str() {
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                exit(0);
+            }
+            "--seed" => seed = Some(parse_num(&value("--seed")?)?),
+            "--slots" => slots = Some(parse_num(&value("--slots")?)?),
+            "--nodes" => nodes = Some(parse_num(&value("--nodes")?)?),
+            "--stakes" => stakes = Some(value("--stakes")?),
+            "--skip-rate" => {
+                let val = value("--skip-rate")?;
+                match val.parse::<f64>() {
+                    Ok(p) if (0.0..=1.0).contains(&p) => skip_rate = Some(p),
+                    _ => return Err(format!("expected a probability, got {:?}", val)),
+                }
+            }
+            "--byzantine" => byzantine = parse_byzantine(&value("--byzantine")?)?,
+            "--latency" => {
+                let val = value("--latency")?;
+                match Delay::parse(&val) {
+                    Some(delay) => latency = Some(delay),
+                    None => return Err(format!("expected a delay, got {:?}", val)),
+                }
+            }
+            "--vote-policy" => {
+                let val = value("--vote-policy")?;
+                vote_policy = Some(VotePolicy::parse(val.split(','))?);
+            }
+            "--leader-window" => leader_window = Some(parse_num(&value("--leader-window")?)?),
+            "--output" => output = Some(value("--output")?),
+            "--events" => events = Some(value("--events")?),
+            "--metrics" => metrics = Some(value("--metrics")?),
+            "--checkpoint" => checkpoint = Some(value("--checkpoint")?),
+            "--checkpoint-every" => {
+                checkpoint_every = Some(parse_num(&value("--checkpoint-every")?)?)
+            }
+            "--restore" => restore = Some(value("--restore")?),
+            "--runs" => runs = parse_num(&value("--runs")?)?,
+            "--repro" => repro = value("--repro")?,
+            "four-partitions" | "partition-test-1" | "random-partitions" if scenario.is_none() => {
+                scenario = Some(match arg.as_str() {
+                    "four-partitions" => Scenario::FourPartitions,
+                    "partition-test-1" => Scenario::PartitionTest1,
+                    _ => Scenario::RandomPartitions,
+                });
+            }
+            "fuzz" if scenario.is_none() => scenario = Some(Scenario::Fuzz),
+            "scenario" if scenario.is_none() => {
+                scenario = Some(Scenario::File(value("scenario")?));
+            }
+            _ => return Err(format!("unexpected argument {:?}", arg)),
+        }
+    }
+    //a restored run continues with the config saved in the snapshot
+    let config_flags = [
+        ("--nodes", nodes.is_some()),
+        ("--stakes", stakes.is_some()),
+        ("--seed", seed.is_some()),
+        ("--leader-window", leader_window.is_some()),
+        ("--skip-rate", skip_rate.is_some()),
+        ("--latency", latency.is_some()),
+        ("--vote-policy", vote_policy.is_some()),
+    ];
+    if let Some((flag, _)) = config_flags
+        .iter()
+        .find(|(_, set)| *set && restore.is_some())
+    {
+        return Err(format!(
+            "--restore uses the snapshot's config, {} can't change it",
+            flag
+        ));
+    }
+    //fuzz generates its own config for every schedule
+    let fuzz_flags = [
+        ("--nodes", nodes.is_some()),
+        ("--stakes", stakes.is_some()),
+        ("--leader-window", leader_window.is_some()),
+        ("--skip-rate", skip_rate.is_some()),
+        ("--latency", latency.is_some()),
+        ("--vote-policy", vote_policy.is_some()),
+        ("--byzantine", !byzantine.is_empty()),
+        ("--slots", slots.is_some()),
+        ("--events", events.is_some()),
+        ("--metrics", metrics.is_some()),
+    ];
+    let mut config = match (stakes, nodes) {
+        (Some(_), Some(_)) => return Err("--stakes and --nodes are exclusive".to_string()),
+        (Some(path), None) => SimConfig {
+            stakes: Stakes::load(Path::new(&path))
+                .map_err(|e| format!("failed to load stakes from {}: {}", path, e))?,
+            ..SimConfig::default()
+        },
+        (None, nodes) => {
+            let nodes = nodes.unwrap_or(NUM_NODES as u64) as usize;
+            if nodes == 0 {
+                return Err("--nodes must be at least 1".to_string());
+            }
+            SimConfig::new(nodes)
+        }
+    };
+    config.seed = seed.unwrap_or(0);
+    if let Some(p) = skip_rate {
+        config.availability = Availability::uniform(p);
+    }
+    if let Some(delay) = latency {
+        config.latency = Latency::uniform(delay);
+    }
+    if let Some(policy) = vote_policy {
+        config.votes = policy;
+    }
+    if let Some(window) = leader_window {
+        config.leader_window = window as usize;
+    }
+    config.validate()?;
+    let scenario = scenario.unwrap_or(Scenario::FourPartitions);
+    if (checkpoint.is_some() || restore.is_some())
+        && !matches!(
+            scenario,
+            Scenario::FourPartitions | Scenario::RandomPartitions
+        )
+    {
+        return Err(
+            "--checkpoint and --restore only work with four-partitions and random-partitions"
+                .to_string(),
+        );
+    }
+    if scenario == Scenario::Fuzz {
+        if let Some((flag, _)) = fuzz_flags.iter().find(|(_, set)| *set) {
+            return Err(format!(
+                "fuzz generates the config of every schedule, {} can't change it",
+                flag
+            ));
+        }
+        if config.seed.checked_add(runs).is_none() {
+            return Err("--seed + --runs is past the last seed".to_string());
+        }
+    }
+    //the warmup of partition-test-1 doesn't rotate the subcommittee, so the primary
+    //it splits into four groups is the first one
+    if scenario == Scenario::PartitionTest1 {
+        let primary = Subcommittee::new(&config).primary.len();
+        if primary < 4 {
+            return Err(format!(
+                "partition-test-1 splits the primary into 4 groups, it has {} nodes",
+                primary
+            ));
+        }
+    }
+    if checkpoint_every == Some(0) || (checkpoint_every.is_some() && checkpoint.is_none()) {
+        return Err("--checkpoint-every needs --checkpoint and at least 1 slot".to_string());
+    }
+    Ok(Args {
+        scenario,
+        slots: slots.map(|s| s as usize),
+        config,
+        output,
+        events,
+        metrics,
+        checkpoint,
+        checkpoint_every,
+        restore,
+        byzantine,
+        runs,
+        repro,
+    })
+}
+
+fn parse_byzantine(val: &str) -> Result<Vec<(VoterKind, f64)>, String> {
+    let mut byzantine = vec![];
+    for entry in val.split(',') {
+        let (kind, fraction) = entry
+            .split_once('=')
+            .ok_or_else(|| format!("expected KIND=F, got {:?}", entry))?;
+        let kind =
+            VoterKind::parse(kind).ok_or_else(|| format!("unknown voter kind {:?}", kind))?;
+        match fraction.parse::<f64>() {
+            Ok(f) if (0.0..=1.0).contains(&f) => byzantine.push((kind, f)),
+            _ => return Err(format!("expected a fraction, got {:?}", fraction)),
+        }
+    }
+    if byzantine.iter().map(|(_, f)| f).sum::<f64>() > 1.0 {
+        return Err("--byzantine fractions add up to more than 1".to_string());
+    }
+    Ok(byzantine)
+}
+
+//a run stops at the first write error or safety violation
+#[derive(Debug)]
+enum RunError {
+    Io(io::Error),
+    Safety(SafetyViolation),
+    Snapshot(SnapshotError),
+    Scenario(String, ScenarioError),
+    //the lowest root didn't advance past `root` once the partitions were repaired
+    Stalled { root: Slot },
+}
+
+impl fmt::Display for RunError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RunError::Io(e) => write!(f, "failed to write the report: {}", e),
+            RunError::Safety(v) => write!(f, "safety violation at {}", v),
+            RunError::Snapshot(e) => write!(f, "failed to save the checkpoint: {}", e),
+            RunError::Scenario(path, e) => write!(f, "scenario {} failed: {}", path, e),
+            RunError::Stalled { root } => {
+                write!(f, "the lowest root stalled at {} after the repair", root)
+            }
+        }
+    }
+}
+
+impl From<io::Error> for RunError {
+    fn from(e: io::Error) -> Self {
+        RunError::Io(e)
+    }
+}
+
+impl From<SafetyViolation> for RunError {
+    fn from(e: SafetyViolation) -> Self {
+        RunError::Safety(e)
+    }
+}
+
+fn parse_num(val: &str) -> Result<u64, String> {
+    val.parse()
+        .map_err(|_| format!("expected a number, got {:?}", val))
+}
+
+fn create(path: &str) -> BufWriter<File> {
+    match File::create(path) {
+        Ok(f) => BufWriter::new(f),
+        Err(e) => {
+            eprintln!("error: failed to create {}: {}", path, e);
+            exit(1);
+        }
+    }
+}
 
 fn main() {
-    //partition_test_1()
-    four_partitions()
+    let args = match parse_args(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("error: {}\n\n{}", e, USAGE);
+            exit(2);
+        }
+    };
+    let mut out: Box<dyn Write> = match &args.output {
+        Some(path) => Box::new(create(path)),
+        None => Box::new(io::stdout()),
+    };
+    if args.scenario == Scenario::Fuzz {
+        exit(fuzz_schedules(&args, &mut out));
+    }
+    let file = match &args.scenario {
+        Scenario::File(path) => Some(load_scenario(path, &args)),
+        _ => None,
+    };
+    let (mut network, resume) = match (&file, &args.restore) {
+        (_, Some(path)) => match Network::restore(Path::new(path)) {
+            Ok((network, driver)) => (network, Some(driver)),
+            Err(e) => {
+                eprintln!("error: failed to restore {}: {}", path, e);
+                exit(2);
+            }
+        },
+        (Some(scenario), None) => (scenario.network(), None),
+        (None, None) => (Network::new(args.config.clone()), None),
+    };
+    //voter strategies aren't part of a snapshot, a restored run picks the same nodes again
+    network.set_voters(&args.byzantine);
+    if let Some(path) = &args.events {
+        network.set_sink(Box::new(JsonLinesSink::new(create(path))));
+    }
+    let rv = match (&args.scenario, &file) {
+        (Scenario::FourPartitions, _) => four_partitions(&mut network, &args, resume, &mut out),
+        (Scenario::PartitionTest1, _) => partition_test_1(&mut network, &args, &mut out),
+        (Scenario::RandomPartitions, _) => random_partitions(&mut network, &args, resume, &mut out),
+        (Scenario::File(path), Some(scenario)) => {
+            scenario_file(&mut network, path, scenario, &mut out)
+        }
+        (Scenario::File(_), None) => unreachable!("scenario files are loaded before the run"),
+        (Scenario::Fuzz, _) => unreachable!("fuzz doesn't step a network"),
+    };
+    //the metrics and event log are still written after a safety violation
+    let report = report_metrics(&network, &args, &mut out)
+        .and_then(|_| out.flush())
+        .and_then(|_| network.sink().flush());
+    if let Err(e) = rv.and(report.map_err(RunError::Io)) {
+        eprintln!("error: {}", e);
+        exit(1);
+    }
+}
+
+//the exit code, 1 if a schedule failed
+fn fuzz_schedules(args: &Args, out: &mut dyn Write) -> i32 {
+    let seed = args.config.seed;
+    let rv = match fuzz::fuzz(seed..seed + args.runs) {
+        None => writeln!(out, "FUZZ {} schedules passed", args.runs).map(|_| 0),
+        Some((schedule, failure)) => {
+            eprintln!("error: schedule {} failed: {}", schedule.seed, failure);
+            fs::write(&args.repro, schedule.to_string())
+                .and_then(|_| writeln!(out, "FUZZ shrunk schedule written to {}", args.repro))
+                .map(|_| 1)
+        }
+    };
+    match rv.and_then(|code| out.flush().map(|_| code)) {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            1
+        }
+    }
+}
+
+fn report_metrics(network: &Network, args: &Args, out: &mut dyn Write) -> io::Result<()> {
+    let summary = network.metrics.summary();
+    write!(out, "{}", summary)?;
+    if let Some(path) = &args.metrics {
+        let mut f = create(path);
+        if path.ends_with(".csv") {
+            f.write_all(summary.to_csv().as_bytes())?;
+        } else {
+            f.write_all(summary.to_json().as_bytes())?;
+        }
+        f.flush()?;
+    }
+    Ok(())
 }
 
-fn partition_test_1() {
-    let mut network = network::Network::default();
+fn partition_test_1(
+    network: &mut Network,
+    args: &Args,
+    out: &mut dyn Write,
+) -> Result<(), RunError> {
+    let phase = args.slots.unwrap_or(512);
     //warmup
-    for _ in 0..DEPTH {
-        network.step(1);
+    for _ in 0..network.config().depth {
+        network.step(1)?;
     }
-    println!("LOWEST ROOT {:?}", network.lowest_root());
+    writeln!(out, "LOWEST ROOT {:?}", network.lowest_root())?;
 
     //                                       /---33 - 34 -35 -36
     // 0 -> 1 -> 2 -> 3 ->... -> 31-> 32
@@ -27,99 +458,184 @@ fn partition_test_1() {
     let max = primary.len();
     let big = 2 * primary.len() / 3 - 1;
     let partitions = [
-        (primary[0], primary[big]),
-        (primary[big], primary[max - 2]),
-        (primary[max - 2], primary[max - 1]),
-        (primary[max - 1], primary[max - 1] + 1),
+        NodeSet::ids(primary[..big].iter().copied()),
+        NodeSet::ids(primary[big..max - 2].iter().copied()),
+        NodeSet::ids([primary[max - 2]]),
+        NodeSet::ids([primary[max - 1]]),
     ];
-    println!("PARTITIONS {:?} {:?}", partitions, primary);
-    println!("LOWEST ROOT {:?}", network.lowest_root());
+    let first = |ix: usize| partitions[ix].first().unwrap();
+    writeln!(out, "PARTITIONS {:?} {:?}", partitions, primary)?;
+    writeln!(out, "LOWEST ROOT {:?}", network.lowest_root())?;
 
     //1. The 1A group votes on slots 0 to 31, so its root stays 0
-    let bp_66 = partitions[0].0;
-    let bp_1a = partitions[3].0;
+    let bp_66 = first(0);
     //2. The 66  group votes 1 to 32 so makes new root at 1-4
-    network.partition_step(&partitions, &[true, false, false, false], bp_66);
-    network.partition_step(&partitions, &[true, false, false, false], bp_66);
-    network.partition_step(&partitions, &[true, false, false, false], bp_66);
-    network.partition_step(&partitions, &[true, false, false, false], bp_66);
-    println!("LOWEST ROOT {:?}", network.lowest_root());
+    network.partition_step(&partitions, &[true, false, false, false], bp_66)?;
+    network.partition_step(&partitions, &[true, false, false, false], bp_66)?;
+    network.partition_step(&partitions, &[true, false, false, false], bp_66)?;
+    network.partition_step(&partitions, &[true, false, false, false], bp_66)?;
+    writeln!(out, "LOWEST ROOT {:?}", network.lowest_root())?;
     //3. All these votes have landed in both forks
 
     //4. Now after the fork,  1B group starts voting on the top fork on slots 0 -> 36, so  it's rooting common ancestors 0 -> 32, updating the SMJRwhen it finally roots 1
-    let bp_1b = partitions[3].0;
+    let bp_1b = first(3);
     network.repair_partitions(&partitions, &[true, false, false, true]);
-    network.partition_step(&partitions, &[false, false, false, true], bp_1b);
-    network.partition_step(&partitions, &[false, false, false, true], bp_1b);
-    network.partition_step(&partitions, &[false, false, false, true], bp_1b);
-    network.partition_step(&partitions, &[false, false, false, true], bp_1b);
-    network.partition_step(&partitions, &[false, false, false, true], bp_1b);
-    network.partition_step(&partitions, &[false, false, false, true], bp_1b);
-    network.partition_step(&partitions, &[false, false, false, true], bp_1b);
-    network.partition_step(&partitions, &[false, false, false, true], bp_1b);
-    network.partition_step(&partitions, &[false, false, false, true], bp_1b);
-    network.partition_step(&partitions, &[false, false, false, true], bp_1b);
-    network.partition_step(&partitions, &[false, false, false, true], bp_1b);
-    network.partition_step(&partitions, &[false, false, false, true], bp_1b);
-    println!("LOWEST ROOT {:?}", network.lowest_root());
+    for _ in 0..12 {
+        network.partition_step(&partitions, &[false, false, false, true], bp_1b)?;
+    }
+    writeln!(out, "LOWEST ROOT {:?}", network.lowest_root())?;
 
     //5. Meanwhile the 32 group at some point starts voting on the bottom fork, making that the heaviest fork
-    let bp_32 = partitions[1].0;
+    let bp_32 = first(1);
     network.repair_partitions(&partitions, &[true, true, false, false]);
-    for _ in 0..512 {
-        network.partition_step(&partitions, &[false, true, false, false], bp_32);
-        println!("LOWEST ROOT {:?}", network.lowest_root());
+    for _ in 0..phase {
+        network.partition_step(&partitions, &[false, true, false, false], bp_32)?;
+        writeln!(out, "LOWEST ROOT {:?}", network.lowest_root())?;
     }
     let root = network.lowest_root();
 
     //partitions reparied
-    for _ in 0..512 {
-        network.step(1);
-        println!("LOWEST ROOT {:?}", network.lowest_root());
+    for _ in 0..phase {
+        network.step(1)?;
+        writeln!(out, "LOWEST ROOT {:?}", network.lowest_root())?;
+    }
+    if network.lowest_root().slot <= root.slot {
+        return Err(RunError::Stalled { root: root.slot });
+    }
+    Ok(())
+}
+
+//the driver state saved with a checkpoint is the scenario tag and the scenario's loop state
+const FOUR_PARTITIONS: u64 = 0;
+const RANDOM_PARTITIONS: u64 = 1;
+
+//the loop state of a restored run, or `fresh` for a new run
+fn resume_state(resume: Option<Vec<u64>>, tag: u64, fresh: Vec<u64>) -> Vec<u64> {
+    match resume {
+        None => fresh,
+        Some(state) if state.len() == fresh.len() + 1 && state[0] == tag => state[1..].to_vec(),
+        Some(_) => {
+            eprintln!("error: the snapshot was saved by a different scenario");
+            exit(2);
+        }
     }
-    assert!(network.lowest_root().slot > root.slot);
 }
 
-fn four_partitions() {
-    let mut network = network::Network::default();
-    let mut num_partitions = 1;
+//saves every --checkpoint-every slots and at the end of the run
+fn checkpoint(network: &Network, args: &Args, done: bool, state: &[u64]) -> Result<(), RunError> {
+    if let Some(path) = &args.checkpoint {
+        let due = args
+            .checkpoint_every
+            .is_some_and(|n| network.slot().is_multiple_of(n));
+        if done || due {
+            network
+                .checkpoint(Path::new(path), state)
+                .map_err(RunError::Snapshot)?;
+        }
+    }
+    Ok(())
+}
+
+fn four_partitions(
+    network: &mut Network,
+    args: &Args,
+    resume: Option<Vec<u64>>,
+    out: &mut dyn Write,
+) -> Result<(), RunError> {
+    let state = resume_state(resume, FOUR_PARTITIONS, vec![1, 0]);
+    let mut num_partitions = state[0] as usize;
     const TIME: usize = 256;
-    let mut partition_slot = 0;
-    for slot in 0..TIME * 100_000 {
-        network.step(num_partitions);
-        println!("LOWEST ROOT {:?}", network.lowest_root());
+    let mut partition_slot = state[1] as usize;
+    let state = |num_partitions: usize, partition_slot: usize| {
+        [
+            FOUR_PARTITIONS,
+            num_partitions as u64,
+            partition_slot as u64,
+        ]
+    };
+    //a restored run picks up after the last stepped slot
+    for slot in network.slot() as usize..args.slots.unwrap_or(TIME * 100_000) {
+        network.step(num_partitions)?;
+        writeln!(out, "LOWEST ROOT {:?}", network.lowest_root())?;
         if num_partitions == 1 && slot >= TIME && slot % TIME == 0 {
-            println!("CREATING PARTITIONS===================================");
+            writeln!(
+                out,
+                "CREATING PARTITIONS==================================="
+            )?;
             num_partitions = 3;
             partition_slot = slot;
         }
         if num_partitions > 1 && partition_slot + TIME / 8 <= slot && slot % (TIME / 8) == 0 {
-            println!("REPAIRING PARTITIONS=================================");
-            num_partitions = num_partitions - 1;
+            writeln!(out, "REPAIRING PARTITIONS=================================")?;
+            num_partitions -= 1;
         }
+        checkpoint(network, args, false, &state(num_partitions, partition_slot))?;
     }
+    checkpoint(network, args, true, &state(num_partitions, partition_slot))
 }
 
-fn random_partitions() {
-    let mut network = network::Network::default();
-    let mut num_partitions = 1;
-    let mut time: usize = 512;
-    let mut partition_slot = 0;
-    let mut repair_time = 32;
-    for slot in 0..100_000 {
-        network.step(num_partitions);
-        println!("LOWEST ROOT {:?}", network.lowest_root());
+fn random_partitions(
+    network: &mut Network,
+    args: &Args,
+    resume: Option<Vec<u64>>,
+    out: &mut dyn Write,
+) -> Result<(), RunError> {
+    let state = resume_state(resume, RANDOM_PARTITIONS, vec![1, 512, 0, 32]);
+    let mut num_partitions = state[0] as usize;
+    let mut time = state[1] as usize;
+    let mut partition_slot = state[2] as usize;
+    let mut repair_time = state[3] as usize;
+    let state = |num_partitions: usize, time: usize, partition_slot: usize, repair_time: usize| {
+        [
+            RANDOM_PARTITIONS,
+            num_partitions as u64,
+            time as u64,
+            partition_slot as u64,
+            repair_time as u64,
+        ]
+    };
+    for slot in network.slot() as usize..args.slots.unwrap_or(100_000) {
+        network.step(num_partitions)?;
+        writeln!(out, "LOWEST ROOT {:?}", network.lowest_root())?;
         if num_partitions <= 1 && slot >= partition_slot + time && slot % time == 0 {
-            println!("CREATING PARTITIONS===================================");
-            let mut rng = StdRng::seed_from_u64(slot as u64);
+            writeln!(
+                out,
+                "CREATING PARTITIONS==================================="
+            )?;
+            let mut rng = network.rng(slot as u64);
             num_partitions = rng.gen_range(2..6);
             time = rng.gen_range(16..512);
             repair_time = rng.gen_range(1..512);
             partition_slot = slot;
         }
         if num_partitions > 1 && partition_slot + repair_time <= slot && slot % repair_time == 0 {
-            println!("REPAIRING PARTITIONS=================================");
-            num_partitions = num_partitions - 1;
+            writeln!(out, "REPAIRING PARTITIONS=================================")?;
+            num_partitions -= 1;
+        }
+        let s = state(num_partitions, time, partition_slot, repair_time);
+        checkpoint(network, args, false, &s)?;
+    }
+    let s = state(num_partitions, time, partition_slot, repair_time);
+    checkpoint(network, args, true, &s)
+}
+
+fn load_scenario(path: &str, args: &Args) -> ScenarioFile {
+    match ScenarioFile::load(Path::new(path), args.config.clone()) {
+        Ok(scenario) => scenario,
+        Err(e) => {
+            eprintln!("error: invalid scenario {}: {}", path, e);
+            exit(2);
         }
     }
 }
+
+fn scenario_file(
+    network: &mut Network,
+    path: &str,
+    scenario: &ScenarioFile,
+    out: &mut dyn Write,
+) -> Result<(), RunError> {
+    let rv = scenario.run(network);
+    writeln!(out, "LOWEST ROOT {:?}", network.lowest_root())?;
+    rv.map_err(|e| RunError::Scenario(path.to_string(), e))
+}
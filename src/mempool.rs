@@ -0,0 +1,312 @@
+//! Vote transactions on their way into blocks.
+//!
+//! Every vote a node casts is sent to the leader of the slot as a transaction
+//! carrying the node's tower. A transaction can be lost in transit, delayed by
+//! the `Latency` of its link and so arrive out of order, and then waits in the
+//! pool until a producer includes it. By default each node's newest tower lands
+//! in every block until a newer one replaces it, `VotePolicy` changes which
+//! transactions a producer picks and whether they land more than once.
+use crate::bank::ID;
+use crate::seed::{self, Stream};
+use crate::snapshot::{corrupt, Reader, SnapshotError, Writer};
+use crate::tower::{Slot, Vote};
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VoteTx {
+    pub from: ID,
+    //the slot the vote was cast in
+    pub sent: Slot,
+    //the voter's tower after voting
+    pub votes: Vec<Vote>,
+    //`Block::id` of the version of the latest vote's slot the voter received
+    pub id: u64,
+}
+
+/// How vote transactions travel and which ones a producer includes.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct VotePolicy {
+    //chance that a transaction is lost before it reaches the leader
+    pub loss: f64,
+    //most transactions in a block, `None` for no limit
+    pub cap: Option<usize>,
+    //over the cap, include the newest transactions first instead of the oldest
+    pub prefer_recent: bool,
+    //transactions sent more than `max_age` slots before a block are dropped
+    pub max_age: Option<Slot>,
+    //a transaction leaves the pool once it lands, towers that arrive late still land
+    pub land_once: bool,
+}
+
+impl VotePolicy {
+    /// options `loss=P`, `cap=N`, `recent`, `max_age=N` and `once`
+    pub fn parse<'a>(opts: impl IntoIterator<Item = &'a str>) -> Result<Self, String> {
+        let mut policy = VotePolicy::default();
+        for opt in opts {
+            let num = |val: &str| {
+                val.parse::<u64>()
+                    .map_err(|_| format!("expected a number, got {:?}", val))
+            };
+            match opt.split_once('=') {
+                Some(("loss", val)) => match val.parse::<f64>() {
+                    Ok(p) if (0.0..=1.0).contains(&p) => policy.loss = p,
+                    _ => return Err(format!("expected a probability, got {:?}", val)),
+                },
+                //both are saved one higher so that 0 can mean no limit
+                Some(("cap", val)) => match usize::try_from(num(val)?) {
+                    Ok(cap) if cap > 0 && cap < usize::MAX => policy.cap = Some(cap),
+                    _ => return Err(format!("cap must be between 1 and {}", usize::MAX - 1)),
+                },
+                Some(("max_age", val)) => match num(val)? {
+                    age if age < u64::MAX => policy.max_age = Some(age),
+                    _ => return Err(format!("max_age must be below {}", u64::MAX)),
+                },
+                None if opt == "recent" => policy.prefer_recent = true,
+                None if opt == "once" => policy.land_once = true,
+                _ => return Err(format!("unknown vote policy option {:?}", opt)),
+            }
+        }
+        Ok(policy)
+    }
+
+    /// whether the vote `from` cast in `slot` is lost, derived from the master seed
+    pub fn lost(&self, seed: u64, from: ID, slot: Slot) -> bool {
+        if self.loss <= 0.0 {
+            return false;
+        }
+        let index = seed::hash(slot ^ seed::hash(from as u64));
+        seed::chance(seed::derive(seed, Stream::Votes, index), self.loss)
+    }
+
+    pub fn encode(&self, w: &mut Writer) {
+        w.u64(self.loss.to_bits());
+        //0 is no limit
+        w.usize(self.cap.map_or(0, |cap| cap + 1));
+        w.bool(self.prefer_recent);
+        w.u64(self.max_age.map_or(0, |age| age + 1));
+        w.bool(self.land_once);
+    }
+
+    pub fn decode(r: &mut Reader) -> Result<Self, SnapshotError> {
+        let loss = f64::from_bits(r.u64()?);
+        if !(0.0..=1.0).contains(&loss) {
+            return corrupt(format!("invalid vote loss {}", loss));
+        }
+        let cap = r.usize()?.checked_sub(1);
+        if cap == Some(0) {
+            return corrupt("invalid vote cap 0");
+        }
+        Ok(VotePolicy {
+            loss,
+            cap,
+            prefer_recent: r.bool()?,
+            max_age: r.u64()?.checked_sub(1),
+            land_once: r.bool()?,
+        })
+    }
+}
+
+//most transactions of one node that wait in the pool when they land once, the
+//oldest are dropped so a long partition doesn't grow the pool without bound
+pub const MAX_PENDING: usize = 32;
+
+/// the vote transactions that reached the leaders and didn't land for good yet
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Mempool {
+    //the transactions of each node ordered by the slot they were sent in
+    txs: BTreeMap<ID, Vec<VoteTx>>,
+    //transactions dropped over `MAX_PENDING` since the last `select`
+    dropped: usize,
+}
+
+impl Mempool {
+    pub fn new(txs: Vec<VoteTx>) -> Self {
+        let mut pool = Mempool::default();
+        for tx in txs {
+            pool.txs.entry(tx.from).or_default().push(tx);
+        }
+        pool
+    }
+
+    pub fn add(&mut self, tx: VoteTx, policy: &VotePolicy) {
+        let pending = self.txs.entry(tx.from).or_default();
+        if !policy.land_once {
+            //an older tower that arrives late doesn't replace a newer one
+            if let Some(old) = pending.first_mut() {
+                if tx.sent >= old.sent {
+                    *old = tx;
+                }
+                return;
+            }
+        }
+        let ix = pending.partition_point(|t| t.sent <= tx.sent);
+        pending.insert(ix, tx);
+        if pending.len() > MAX_PENDING {
+            pending.remove(0);
+            self.dropped += 1;
+        }
+    }
+
+    /// Drop the transactions that are too old for a block in `slot` and pick the
+    /// ones to include out of those `reaches` the producer. Returns the picks,
+    /// ordered by node and then by age, and the number of stale transactions.
+    pub fn select(
+        &mut self,
+        policy: &VotePolicy,
+        slot: Slot,
+        mut reaches: impl FnMut(&VoteTx) -> bool,
+    ) -> (Vec<VoteTx>, usize) {
+        let mut stale = std::mem::take(&mut self.dropped);
+        if let Some(age) = policy.max_age {
+            for pending in self.txs.values_mut() {
+                let before = pending.len();
+                pending.retain(|t| t.sent.saturating_add(age) >= slot);
+                stale += before - pending.len();
+            }
+            self.txs.retain(|_, pending| !pending.is_empty());
+        }
+        let mut picked: Vec<&VoteTx> = self.txs.values().flatten().filter(|t| reaches(t)).collect();
+        if let Some(cap) = policy.cap {
+            if policy.prefer_recent {
+                picked.sort_by_key(|t| (Reverse(t.sent), t.from));
+            } else {
+                picked.sort_by_key(|t| (t.sent, t.from));
+            }
+            picked.truncate(cap);
+            picked.sort_by_key(|t| (t.from, t.sent));
+        }
+        (picked.into_iter().cloned().collect(), stale)
+    }
+
+    /// The picked transactions that are in `votes`, the votes of a block that
+    /// was built from them. They leave the pool if they land once.
+    pub fn landed(
+        &mut self,
+        picked: Vec<VoteTx>,
+        votes: &[(ID, Vec<Vote>)],
+        policy: &VotePolicy,
+    ) -> Vec<VoteTx> {
+        let mut by_node: HashMap<ID, Vec<&Vec<Vote>>> = HashMap::new();
+        for (id, votes) in votes {
+            by_node.entry(*id).or_default().push(votes);
+        }
+        let landed: Vec<_> = picked
+            .into_iter()
+            .filter(|t| by_node.get(&t.from).is_some_and(|v| v.contains(&&t.votes)))
+            .collect();
+        if policy.land_once {
+            for tx in &landed {
+                if let Some(pending) = self.txs.get_mut(&tx.from) {
+                    pending.retain(|t| t != tx);
+                    if pending.is_empty() {
+                        self.txs.remove(&tx.from);
+                    }
+                }
+            }
+        }
+        landed
+    }
+
+    pub fn len(&self) -> usize {
+        self.txs.values().map(|pending| pending.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.txs.is_empty()
+    }
+
+    pub fn encode(&self, w: &mut Writer) {
+        w.usize(self.len());
+        for tx in self.txs.values().flatten() {
+            w.usize(tx.from);
+            w.u64(tx.sent);
+            w.votes(&tx.votes);
+            w.u64(tx.id);
+        }
+        w.usize(self.dropped);
+    }
+
+    pub fn decode(r: &mut Reader, num_nodes: usize) -> Result<Self, SnapshotError> {
+        let mut pool = Mempool::default();
+        for _ in 0..r.count()? {
+            let from = r.usize()?;
+            if from >= num_nodes {
+                return corrupt(format!("node {} is out of range", from));
+            }
+            let tx = VoteTx {
+                from,
+                sent: r.u64()?,
+                votes: r.votes()?,
+                id: r.u64()?,
+            };
+            let pending = pool.txs.entry(from).or_default();
+            if pending.last().is_some_and(|t| t.sent > tx.sent) {
+                return corrupt(format!("transactions of node {} are out of order", from));
+            }
+            pending.push(tx);
+        }
+        pool.dropped = r.usize()?;
+        Ok(pool)
+    }
+}
+
+#[test]
+fn test_inclusion_policy() {
+    let tx = |from, sent| VoteTx {
+        from,
+        sent,
+        votes: vec![Vote::new(sent)],
+        id: 0,
+    };
+    //newer towers replace older ones, a late older tower is dropped
+    let latest = VotePolicy::default();
+    let mut pool = Mempool::default();
+    for t in [tx(1, 3), tx(0, 2), tx(1, 5), tx(1, 4)] {
+        pool.add(t, &latest);
+    }
+    let (picked, stale) = pool.select(&latest, 6, |_| true);
+    assert_eq!(picked, vec![tx(0, 2), tx(1, 5)]);
+    assert_eq!(stale, 0);
+    //they land again in the next block
+    pool.landed(picked.clone(), &[(1, vec![Vote::new(5)])], &latest);
+    assert_eq!(pool.select(&latest, 7, |_| true).0, picked);
+
+    let policy = VotePolicy::parse(["cap=2", "recent", "max_age=3", "once"]).unwrap();
+    let mut pool = Mempool::default();
+    for t in [tx(0, 1), tx(1, 4), tx(2, 5), tx(0, 6), tx(1, 3)] {
+        pool.add(t, &policy);
+    }
+    assert_eq!(pool.len(), 5);
+    let (picked, stale) = pool.select(&policy, 6, |t| t.from != 2);
+    assert_eq!(stale, 1);
+    assert_eq!(picked, vec![tx(0, 6), tx(1, 4)]);
+    let votes: Vec<_> = picked.iter().map(|t| (t.from, t.votes.clone())).collect();
+    let landed = pool.landed(picked, &votes[..1], &policy);
+    assert_eq!(landed, vec![tx(0, 6)]);
+    let (picked, _) = pool.select(&policy, 6, |_| true);
+    assert_eq!(picked, vec![tx(1, 4), tx(2, 5)]);
+    let votes: Vec<_> = picked.iter().map(|t| (t.from, t.votes.clone())).collect();
+    pool.landed(picked, &votes, &policy);
+    //the tower of node 1 that arrived out of order still lands
+    assert_eq!(pool.select(&policy, 6, |_| true).0, vec![tx(1, 3)]);
+    //a node that keeps voting during a partition doesn't grow the pool without bound
+    let once = VotePolicy::parse(["once"]).unwrap();
+    let mut pool = Mempool::default();
+    for sent in 0..(MAX_PENDING as u64 + 8) {
+        pool.add(tx(3, sent), &once);
+    }
+    assert_eq!(pool.len(), MAX_PENDING);
+    let (picked, stale) = pool.select(&once, 100, |_| true);
+    assert_eq!(stale, 8);
+    assert_eq!(picked.first(), Some(&tx(3, 8)));
+    assert!(VotePolicy::parse(["loss=2"]).is_err());
+    assert!(VotePolicy::parse(["cap=0"]).is_err());
+    assert!(VotePolicy::parse([format!("cap={}", u64::MAX).as_str()]).is_err());
+    assert!(VotePolicy::parse([format!("max_age={}", u64::MAX).as_str()]).is_err());
+    let policy = VotePolicy::parse([format!("max_age={}", u64::MAX - 1).as_str()]).unwrap();
+    let mut pool = Mempool::new(vec![tx(0, 5)]);
+    assert_eq!(pool.select(&policy, 6, |_| true).0, vec![tx(0, 5)]);
+    assert!(VotePolicy::parse(["bogus"]).is_err());
+}
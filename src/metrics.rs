@@ -0,0 +1,312 @@
+use crate::events::Event;
+use crate::node::Refusal;
+use crate::snapshot::{Reader, SnapshotError, Writer};
+use crate::subcommittee::Phase;
+use crate::tower::Slot;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+
+/// value -> count
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Histogram {
+    counts: BTreeMap<u64, u64>,
+    total: u64,
+    sum: u128,
+}
+
+impl Histogram {
+    pub fn record(&mut self, val: u64) {
+        self.record_n(val, 1);
+    }
+    pub fn record_n(&mut self, val: u64, n: u64) {
+        if n == 0 {
+            return;
+        }
+        *self.counts.entry(val).or_insert(0) += n;
+        self.total += n;
+        self.sum += val as u128 * n as u128;
+    }
+    pub fn count(&self) -> u64 {
+        self.total
+    }
+    pub fn min(&self) -> u64 {
+        self.counts.keys().next().cloned().unwrap_or(0)
+    }
+    pub fn max(&self) -> u64 {
+        self.counts.keys().next_back().cloned().unwrap_or(0)
+    }
+    pub fn mean(&self) -> f64 {
+        if self.total == 0 {
+            return 0.0;
+        }
+        self.sum as f64 / self.total as f64
+    }
+    pub fn encode(&self, w: &mut Writer) {
+        w.usize(self.counts.len());
+        for (val, count) in &self.counts {
+            w.u64(*val);
+            w.u64(*count);
+        }
+    }
+
+    pub fn decode(r: &mut Reader) -> Result<Self, SnapshotError> {
+        let mut h = Histogram::default();
+        for _ in 0..r.count()? {
+            let val = r.u64()?;
+            let count = r.u64()?;
+            h.counts.insert(val, count);
+            h.total += count;
+            h.sum += val as u128 * count as u128;
+        }
+        Ok(h)
+    }
+
+    /// smallest value that is at least `pct` percent of the samples
+    pub fn percentile(&self, pct: u64) -> u64 {
+        let target = (self.total * pct).div_ceil(100).max(1);
+        let mut seen = 0;
+        for (val, count) in &self.counts {
+            seen += count;
+            if seen >= target {
+                return *val;
+            }
+        }
+        0
+    }
+}
+
+/// Run health counters, fed with every event the network records.
+#[derive(Default)]
+pub struct Metrics {
+    pub slots: u64,
+    pub blocks: u64,
+    pub skipped: u64,
+    //blocks for a slot that already had one
+    pub duplicates: u64,
+    //evidence of lockout violations, double votes and equivocation
+    pub slashable: u64,
+    pub votes_cast: u64,
+    //vote transactions lost on the way to the leader
+    pub votes_lost: u64,
+    //vote transactions dropped from the pool for being too old
+    pub votes_stale: u64,
+    pub refusals: HashMap<Refusal, u64>,
+    //slots from a block being produced to it being rooted
+    pub root_latency: Histogram,
+    //max node root - lowest primary root at every root update
+    pub root_distance: Histogram,
+    //slots from a vote being cast to landing in a block, for every landing
+    pub vote_delay: Histogram,
+    //blocks built on a parent that already had a child
+    pub forks: u64,
+    //blocks that were pruned without being rooted
+    pub orphaned: u64,
+    pub primary_flips: u64,
+    pub secondary_swaps: u64,
+    pub lowest_root: Slot,
+    //parents that have a child, above the lowest root
+    parents: HashSet<Slot>,
+}
+
+impl Metrics {
+    pub fn record(&mut self, event: &Event) {
+        match event {
+            Event::BlockApplied { slot, parent, .. } => {
+                self.slots = self.slots.max(*slot);
+                self.blocks += 1;
+                if !self.parents.insert(*parent) {
+                    self.forks += 1;
+                }
+            }
+            Event::VoteTally { voted, refused, .. } => {
+                self.votes_cast += *voted as u64;
+                for (r, count) in refused {
+                    *self.refusals.entry(*r).or_insert(0) += *count as u64;
+                }
+            }
+            Event::VoteLost { .. } => self.votes_lost += 1,
+            Event::VotesLanded { delays, stale, .. } => {
+                for (delay, count) in delays {
+                    self.vote_delay.record_n(*delay, *count as u64);
+                }
+                self.votes_stale += *stale as u64;
+            }
+            Event::RootAdvanced {
+                slot,
+                to,
+                max_root,
+                rooted,
+                ..
+            } => {
+                for r in rooted {
+                    self.root_latency.record(slot - r);
+                }
+                self.root_distance.record(max_root - to);
+                self.lowest_root = *to;
+                self.parents.retain(|p| p >= to);
+            }
+            Event::SubcommitteeRotated { phase, .. } => match phase {
+                Phase::FlipPrimary => self.primary_flips += 1,
+                Phase::SwapSecondary => self.secondary_swaps += 1,
+            },
+            Event::SlotSkipped { slot, .. } => {
+                self.slots = self.slots.max(*slot);
+                self.skipped += 1;
+            }
+            Event::DuplicateBlock { .. } => self.duplicates += 1,
+            Event::Slashable { .. } => self.slashable += 1,
+            Event::Gc { orphaned, .. } => self.orphaned += *orphaned as u64,
+            Event::VoteCast { .. }
+            | Event::VoteRefused { .. }
+            | Event::SuperRootAdvanced { .. }
+            | Event::OcObserved { .. } => (),
+        }
+    }
+
+    pub fn encode(&self, w: &mut Writer) {
+        for v in [
+            self.slots,
+            self.blocks,
+            self.skipped,
+            self.duplicates,
+            self.slashable,
+            self.votes_cast,
+            self.votes_lost,
+            self.votes_stale,
+            self.forks,
+            self.orphaned,
+            self.primary_flips,
+            self.secondary_swaps,
+            self.lowest_root,
+        ] {
+            w.u64(v);
+        }
+        for r in Refusal::ALL {
+            w.u64(*self.refusals.get(&r).unwrap_or(&0));
+        }
+        self.root_latency.encode(w);
+        self.root_distance.encode(w);
+        self.vote_delay.encode(w);
+        w.set(&self.parents);
+    }
+
+    pub fn decode(r: &mut Reader) -> Result<Self, SnapshotError> {
+        let mut m = Metrics {
+            slots: r.u64()?,
+            blocks: r.u64()?,
+            skipped: r.u64()?,
+            duplicates: r.u64()?,
+            slashable: r.u64()?,
+            votes_cast: r.u64()?,
+            votes_lost: r.u64()?,
+            votes_stale: r.u64()?,
+            forks: r.u64()?,
+            orphaned: r.u64()?,
+            primary_flips: r.u64()?,
+            secondary_swaps: r.u64()?,
+            lowest_root: r.u64()?,
+            ..Metrics::default()
+        };
+        for kind in Refusal::ALL {
+            let count = r.u64()?;
+            if count > 0 {
+                m.refusals.insert(kind, count);
+            }
+        }
+        m.root_latency = Histogram::decode(r)?;
+        m.root_distance = Histogram::decode(r)?;
+        m.vote_delay = Histogram::decode(r)?;
+        m.parents = r.set()?;
+        Ok(m)
+    }
+
+    pub fn summary(&self) -> Summary {
+        let refusal = |r| *self.refusals.get(&r).unwrap_or(&0);
+        let mut rows = vec![];
+        let mut row = |name: &str, val: String| rows.push((name.to_string(), val));
+        row("slots", self.slots.to_string());
+        row("blocks", self.blocks.to_string());
+        row("skipped_slots", self.skipped.to_string());
+        row("duplicate_blocks", self.duplicates.to_string());
+        row("slashable_votes", self.slashable.to_string());
+        row("lowest_root", self.lowest_root.to_string());
+        row("votes_cast", self.votes_cast.to_string());
+        row("votes_lost", self.votes_lost.to_string());
+        row("votes_stale", self.votes_stale.to_string());
+        for r in Refusal::ALL {
+            row(&format!("refused_{}", r.name()), refusal(r).to_string());
+        }
+        row("forks", self.forks.to_string());
+        row("orphaned_blocks", self.orphaned.to_string());
+        row("primary_flips", self.primary_flips.to_string());
+        row("secondary_swaps", self.secondary_swaps.to_string());
+        for (name, h) in [
+            ("root_latency", &self.root_latency),
+            ("root_distance", &self.root_distance),
+            ("vote_delay", &self.vote_delay),
+        ] {
+            row(&format!("{}_count", name), h.count().to_string());
+            row(&format!("{}_mean", name), format!("{:.2}", h.mean()));
+            row(&format!("{}_min", name), h.min().to_string());
+            row(&format!("{}_p50", name), h.percentile(50).to_string());
+            row(&format!("{}_p99", name), h.percentile(99).to_string());
+            row(&format!("{}_max", name), h.max().to_string());
+        }
+        Summary { rows }
+    }
+}
+
+/// flat list of named values
+pub struct Summary {
+    pub rows: Vec<(String, String)>,
+}
+
+impl Summary {
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.rows
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn to_csv(&self) -> String {
+        let mut s = "metric,value\n".to_string();
+        for (name, val) in &self.rows {
+            s.push_str(&format!("{},{}\n", name, val));
+        }
+        s
+    }
+
+    pub fn to_json(&self) -> String {
+        let rows: Vec<_> = self
+            .rows
+            .iter()
+            .map(|(name, val)| format!("  \"{}\": {}", name, val))
+            .collect();
+        format!("{{\n{}\n}}\n", rows.join(",\n"))
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let width = self.rows.iter().map(|(n, _)| n.len()).max().unwrap_or(0);
+        for (name, val) in &self.rows {
+            writeln!(f, "{:width$}  {}", name, val, width = width)?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_histogram() {
+    let mut h = Histogram::default();
+    for v in [1, 2, 2, 3, 10] {
+        h.record(v);
+    }
+    assert_eq!(h.count(), 5);
+    assert_eq!(h.min(), 1);
+    assert_eq!(h.max(), 10);
+    assert_eq!(h.percentile(50), 2);
+    assert_eq!(h.percentile(99), 10);
+    assert!((h.mean() - 3.6).abs() < 1e-9);
+}
@@ -1,97 +1,442 @@
-use crate::bank::ID;
-use crate::bank::NUM_NODES;
-use crate::forks::Forks;
-use crate::node::Node;
-use crate::subcommittee::hash;
+use crate::bank::{BankKey, Block, ID};
+use crate::config::SimConfig;
+use crate::connectivity::{Connectivity, Graph, Kind, NodeSet, Partitions};
+use crate::events::{Event, EventSink, NullSink};
+use crate::forks::{Applied, Forks};
+use crate::leader_schedule::LeaderSchedule;
+use crate::mempool::{Mempool, VoteTx};
+use crate::metrics::Metrics;
+use crate::node::{Node, Refusal, VoteOutcome};
+use crate::producer::{Delivery, Honest, ProducerStrategy};
+use crate::safety::SafetyViolation;
+use crate::seed::{self, Stream};
+use crate::snapshot::{corrupt, Reader, SnapshotError, Writer};
 use crate::tower::Slot;
 use crate::tower::Vote;
+use crate::voter::{self, VoterKind, VoterStrategy};
+use rand_chacha::ChaCha8Rng;
 use rayon::prelude::*;
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::collections::BinaryHeap;
+use std::collections::HashMap;
 use std::collections::HashSet;
 use std::collections::VecDeque;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+/// a block or vote that hasn't arrived yet, ordered by arrival and then by send order
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct Message {
+    arrival: Slot,
+    seq: u64,
+    payload: Payload,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Payload {
+    //the version `id` of block `slot`
+    Block {
+        to: ID,
+        slot: Slot,
+        id: u64,
+    },
+    //the voter's tower after voting in `sent`, `id` is the version of the latest vote
+    Votes {
+        from: ID,
+        sent: Slot,
+        votes: Vec<Vote>,
+        id: u64,
+    },
+}
 
 pub struct Network {
     nodes: Vec<Node>,
     pub forks: Forks,
     slot: Slot,
-    partitioned_blocks: VecDeque<(ID, Slot)>,
-    oc_slots: HashSet<Slot>,
+    partitioned_blocks: VecDeque<(ID, BankKey)>,
+    oc_slots: HashSet<BankKey>,
+    in_flight: BinaryHeap<Reverse<Message>>,
+    //number of messages sent
+    seq: u64,
+    //vote transactions that reached the leaders
+    pool: Mempool,
+    sink: Box<dyn EventSink>,
+    pub metrics: Metrics,
+    //derived from the config, not part of a snapshot
+    leaders: LeaderSchedule,
+    //leaders that don't follow the honest strategy, not part of a snapshot
+    producers: HashMap<ID, Box<dyn ProducerStrategy>>,
+    //nodes that don't vote honestly, not part of a snapshot
+    voters: HashMap<ID, Box<dyn VoterStrategy>>,
 }
 impl Default for Network {
     fn default() -> Self {
+        Self::new(SimConfig::default())
+    }
+}
+impl Network {
+    pub fn new(config: SimConfig) -> Self {
         let mut nodes = vec![];
-        for i in 0..NUM_NODES {
-            nodes.push(Node::zero(i));
+        for i in 0..config.num_nodes() {
+            nodes.push(Node::zero(i, &config));
         }
+        let pool = Mempool::new(
+            nodes
+                .iter()
+                .map(|n| VoteTx {
+                    from: n.id,
+                    sent: 0,
+                    votes: n.votes(),
+                    id: 0,
+                })
+                .collect(),
+        );
         Network {
-            forks: Forks::default(),
+            leaders: LeaderSchedule::new(&config),
+            producers: HashMap::new(),
+            voters: HashMap::new(),
+            forks: Forks::new(Arc::new(config)),
             nodes,
             slot: 0,
             partitioned_blocks: VecDeque::new(),
             oc_slots: HashSet::new(),
+            in_flight: BinaryHeap::new(),
+            seq: 0,
+            pool,
+            sink: Box::new(NullSink),
+            metrics: Metrics::default(),
         }
     }
-}
-impl Network {
+
+    /// Serialize the full network state. `driver` is opaque state of whatever is
+    /// stepping the network, such as a partition schedule, returned on restore.
+    /// The event sink is not part of the snapshot.
+    pub fn snapshot(&self, driver: &[u64]) -> Vec<u8> {
+        let mut w = Writer::new();
+        self.config().encode(&mut w);
+        w.u64(self.slot);
+        for n in &self.nodes {
+            n.encode(&mut w);
+        }
+        self.forks.encode(&mut w);
+        w.usize(self.partitioned_blocks.len());
+        for (bp, (slot, id)) in &self.partitioned_blocks {
+            w.usize(*bp);
+            w.u64(*slot);
+            w.u64(*id);
+        }
+        let mut oc_slots: Vec<_> = self.oc_slots.iter().cloned().collect();
+        oc_slots.sort_unstable();
+        w.pairs(&oc_slots);
+        w.u64(self.seq);
+        w.usize(self.in_flight.len());
+        for Reverse(m) in self.in_flight.clone().into_sorted_vec().iter().rev() {
+            w.u64(m.arrival);
+            w.u64(m.seq);
+            match &m.payload {
+                Payload::Block { to, slot, id } => {
+                    w.u64(0);
+                    w.usize(*to);
+                    w.u64(*slot);
+                    w.u64(*id);
+                }
+                Payload::Votes {
+                    from,
+                    sent,
+                    votes,
+                    id,
+                } => {
+                    w.u64(1);
+                    w.usize(*from);
+                    w.u64(*sent);
+                    w.votes(votes);
+                    w.u64(*id);
+                }
+            }
+        }
+        self.pool.encode(&mut w);
+        self.metrics.encode(&mut w);
+        w.u64s(driver);
+        w.buf
+    }
+
+    /// Rebuild a network from `snapshot`, stepping it continues the run exactly
+    /// as the original would have. Events go to a `NullSink` until a sink is set,
+    /// and producer and voter strategies have to be set again.
+    pub fn from_snapshot(snapshot: &[u8]) -> Result<(Self, Vec<u64>), SnapshotError> {
+        let mut r = Reader::new(snapshot)?;
+        let config = Arc::new(SimConfig::decode(&mut r)?);
+        let slot = r.u64()?;
+        let mut nodes = vec![];
+        for id in 0..config.num_nodes() {
+            let node = Node::decode(&mut r)?;
+            if node.id != id {
+                return corrupt(format!("node {} is stored as node {}", node.id, id));
+            }
+            nodes.push(node);
+        }
+        let forks = Forks::decode(&mut r, config)?;
+        let mut partitioned_blocks = VecDeque::new();
+        for _ in 0..r.count()? {
+            partitioned_blocks.push_back((r.usize()?, (r.u64()?, r.u64()?)));
+        }
+        let oc_slots = r.pairs()?.into_iter().collect();
+        let num_nodes = forks.config.num_nodes();
+        let node_id = |r: &mut Reader| match r.usize()? {
+            id if id < num_nodes => Ok(id),
+            id => corrupt(format!("node {} is out of range", id)),
+        };
+        let seq = r.u64()?;
+        let mut in_flight = BinaryHeap::new();
+        for _ in 0..r.count()? {
+            let (arrival, seq) = (r.u64()?, r.u64()?);
+            let payload = match r.u64()? {
+                0 => Payload::Block {
+                    to: node_id(&mut r)?,
+                    slot: r.u64()?,
+                    id: r.u64()?,
+                },
+                1 => Payload::Votes {
+                    from: node_id(&mut r)?,
+                    sent: r.u64()?,
+                    votes: r.votes()?,
+                    id: r.u64()?,
+                },
+                tag => return corrupt(format!("invalid message tag {}", tag)),
+            };
+            in_flight.push(Reverse(Message {
+                arrival,
+                seq,
+                payload,
+            }));
+        }
+        let pool = Mempool::decode(&mut r, num_nodes)?;
+        let metrics = Metrics::decode(&mut r)?;
+        let driver = r.u64s()?;
+        r.finish()?;
+        let network = Network {
+            leaders: LeaderSchedule::new(&forks.config),
+            producers: HashMap::new(),
+            voters: HashMap::new(),
+            nodes,
+            forks,
+            slot,
+            partitioned_blocks,
+            oc_slots,
+            in_flight,
+            seq,
+            pool,
+            sink: Box::new(NullSink),
+            metrics,
+        };
+        Ok((network, driver))
+    }
+
+    /// write a snapshot to `path`, replacing any previous one only once it is complete
+    pub fn checkpoint(&self, path: &Path, driver: &[u64]) -> Result<(), SnapshotError> {
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, self.snapshot(driver))?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    pub fn restore(path: &Path) -> Result<(Self, Vec<u64>), SnapshotError> {
+        Self::from_snapshot(&fs::read(path)?)
+    }
+
+    /// blocks produced by `id` are built by `strategy` instead of honestly
+    pub fn set_producer(&mut self, id: ID, strategy: Box<dyn ProducerStrategy>) {
+        self.producers.insert(id, strategy);
+    }
+
+    /// `id` votes with `strategy` instead of honestly
+    pub fn set_voter(&mut self, id: ID, strategy: Box<dyn VoterStrategy>) {
+        self.voters.insert(id, strategy);
+    }
+
+    /// give each strategy to nodes with a `fraction` of the stake, picked from the seed,
+    /// see `voter::assign`
+    pub fn set_voters(&mut self, fractions: &[(VoterKind, f64)]) {
+        let assigned = voter::assign(self.seed(), &self.config().stakes, fractions);
+        for (id, kind) in assigned {
+            self.set_voter(id, kind.strategy());
+        }
+    }
+
+    /// replace the event sink, events are dropped by default
+    pub fn set_sink(&mut self, sink: Box<dyn EventSink>) {
+        self.sink = sink;
+    }
+
+    pub fn sink(&mut self) -> &mut dyn EventSink {
+        self.sink.as_mut()
+    }
+
+    fn record(&mut self, event: Event) {
+        self.metrics.record(&event);
+        self.sink.record(&event);
+    }
+
+    /// Advance one slot with only the `active` partitions live.
+    /// After a safety violation the rest of the slot is skipped, see `SafetyViolation`.
     pub fn partition_step(
         &mut self,
-        partitions: &[(usize, usize)],
+        partitions: &[NodeSet],
         active: &[bool],
         block_producer_ix: usize,
-    ) {
-        self.slot = self.slot + 1;
-        self.repair_partitions(partitions, active);
-        self.vote(partitions, active);
-        let block_producer = &self.nodes[block_producer_ix];
+    ) -> Result<(), SafetyViolation> {
+        let partitions = Partitions { partitions, active };
+        self.step_with(&partitions, block_producer_ix)
+    }
 
-        let votes: Vec<_> = self
-            .nodes
-            .iter()
-            .enumerate()
-            .filter_map(|(i, n)| {
-                if !Self::check_same_partition(partitions, active, block_producer_ix, i) {
-                    return None;
-                }
-                let votes = n.votes();
-                Some((i, votes))
-            })
+    /// Advance one slot over `graph`, the scheduled leader produces.
+    pub fn graph_step(&mut self, graph: &Graph) -> Result<(), SafetyViolation> {
+        let leader = self.leader(self.slot + 1);
+        self.step_with(graph, leader)
+    }
+
+    /// Advance one slot, only the nodes `connectivity` reaches see the blocks of
+    /// `block_producer_ix` and only the votes that reach it land.
+    pub fn step_with(
+        &mut self,
+        connectivity: &dyn Connectivity,
+        block_producer_ix: ID,
+    ) -> Result<(), SafetyViolation> {
+        self.slot += 1;
+        self.deliver_arrived();
+        self.repair(connectivity);
+        self.vote(connectivity, block_producer_ix)?;
+        let config = self.config();
+        if config
+            .availability
+            .skips(config.seed, block_producer_ix, self.slot)
+        {
+            //nothing changes in the forks, votes land in the next block
+            self.record(Event::SlotSkipped {
+                slot: self.slot,
+                leader: block_producer_ix,
+            });
+            return Ok(());
+        }
+        let block_producer = &self.nodes[block_producer_ix];
+        let (seed, slot) = (self.seed(), self.slot);
+        let policy = &self.forks.config.votes;
+        let (picked, stale) = self.pool.select(policy, slot, |tx| {
+            connectivity.reaches(seed, Kind::Vote, tx.from, block_producer_ix, slot)
+        });
+        let votes = picked.clone();
+        let reached: Vec<ID> = (0..self.nodes.len())
+            .filter(|i| connectivity.reaches(seed, Kind::Block, block_producer_ix, *i, slot))
             .collect();
-        let block = block_producer.make_block(self.slot, votes);
-        self.forks.apply(&block);
-        let oc_slots = self.forks.fork_map.get(&block.slot).unwrap().oc_slots();
-        self.oc_slots.extend(&oc_slots);
-        self.nodes.iter_mut().enumerate().for_each(|(i, n)| {
-            if Self::check_same_partition(partitions, active, block_producer_ix, i) {
-                n.set_active_block(self.slot);
+        let blocks = match self.producers.get_mut(&block_producer_ix) {
+            Some(strategy) => {
+                strategy.produce(block_producer, self.slot, votes, &reached, &self.forks)
+            }
+            None => Honest.produce(block_producer, self.slot, votes, &reached, &self.forks),
+        };
+        for (block, delivery) in blocks {
+            assert_eq!(block.slot, self.slot, "block produced for the wrong slot");
+            let rv = self.forks.apply(&block);
+            for event in std::mem::take(&mut self.forks.events) {
+                self.record(event);
+            }
+            let key = (block.slot, block.id());
+            //every version is sent to its recipients, who vote on the one they received
+            match rv? {
+                Applied::New => self.land(&block, key, &picked, stale),
+                Applied::Known => (),
+                Applied::Pruned => continue,
+            }
+            //blocks that don't reach every node are repaired once they can, other
+            //versions of the slot are not
+            let missed = reached.len() < self.nodes.len();
+            for &i in &reached {
+                let sent = match &delivery {
+                    Delivery::All => true,
+                    Delivery::Nodes(ids) => ids.contains(&i),
+                };
+                if sent {
+                    let config = self.config();
+                    let delay = config.latency.delay(seed, block_producer_ix, i, slot);
+                    self.send(
+                        delay,
+                        Payload::Block {
+                            to: i,
+                            slot,
+                            id: key.1,
+                        },
+                    );
+                }
+            }
+            if missed && delivery == Delivery::All {
+                self.partitioned_blocks.push_back((block_producer_ix, key));
             }
-        });
-        let num_dead_partitions: usize = active.iter().map(|x| !(*x) as usize).sum();
-        if num_dead_partitions > 0 {
-            self.partitioned_blocks
-                .push_back((block_producer_ix, block.slot));
         }
         let lowest_root = self.lowest_root().slot;
-        self.partitioned_blocks.retain(|(_, b)| *b >= lowest_root);
-        println!("OC SLOTS {:?}", self.oc_slots);
-        self.oc_slots.retain(|s| !self.forks.roots.contains(s));
-        for s in &self.oc_slots {
-            assert!(*s >= lowest_root, "OC failed {}", *s);
+        self.partitioned_blocks.retain(|(_, b)| b.0 >= lowest_root);
+        let roots = &self.forks.roots;
+        self.oc_slots.retain(|(s, id)| roots.get(s) != Some(id));
+        if let Some(oc_slot) = self.oc_slots.iter().filter(|k| k.0 < lowest_root).min() {
+            //reported once, the next steps check the remaining slots
+            let oc_slot = *oc_slot;
+            self.oc_slots.remove(&oc_slot);
+            return Err(SafetyViolation::OcFailed {
+                slot: self.slot,
+                oc_slot: oc_slot.0,
+                lowest_root,
+            });
         }
+        Ok(())
     }
 
-    pub fn step(&mut self, num_partitions: usize) {
-        let block_producer_ix = hash(self.slot) as usize % self.nodes.len();
+    //record the votes that landed in the new bank `key` and the slots it confirmed
+    fn land(&mut self, block: &Block, key: BankKey, picked: &[VoteTx], stale: usize) {
+        let policy = &self.forks.config.votes;
+        let landed = self.pool.landed(picked.to_vec(), &block.votes, policy);
+        let mut delays: BTreeMap<Slot, usize> = BTreeMap::new();
+        for tx in &landed {
+            *delays.entry(block.slot - tx.sent).or_insert(0) += 1;
+        }
+        self.record(Event::VotesLanded {
+            slot: block.slot,
+            delays: delays.into_iter().collect(),
+            stale,
+        });
+        let oc_slots = self.forks.fork_map[&key].oc_slots();
+        if !oc_slots.is_empty() {
+            let mut sorted: Vec<_> = oc_slots.iter().cloned().collect();
+            sorted.sort_unstable();
+            self.record(Event::OcObserved {
+                slot: block.slot,
+                oc_slots: sorted,
+            });
+        }
+        for s in oc_slots {
+            let id = self
+                .forks
+                .version(key, s)
+                .expect("a confirmed slot is in the fork");
+            self.oc_slots.insert((s, id));
+        }
+    }
+
+    pub fn step(&mut self, num_partitions: usize) -> Result<(), SafetyViolation> {
+        let num_nodes = self.nodes.len();
+        let block_producer_ix = self.leader(self.slot + 1);
         let mut partitions = vec![];
         for i in 0..num_partitions {
-            let num = NUM_NODES / num_partitions;
+            let num = num_nodes / num_partitions;
             assert!(num > 0, "invalid number of partitions");
             let min = i * num;
             let mut max = (i + 1) * num;
             if i == num_partitions - 1 {
-                max = NUM_NODES;
+                max = num_nodes;
             }
             partitions.push((min, max));
         }
-        assert_eq!(partitions.last().unwrap().1, NUM_NODES);
+        assert_eq!(partitions.last().unwrap().1, num_nodes);
         let mut active = vec![];
         for (s, e) in &partitions {
             if block_producer_ix >= *s && block_producer_ix < *e {
@@ -101,51 +446,547 @@ impl Network {
             }
         }
         assert_eq!(active.iter().filter(|x| **x).count(), 1);
-        self.partition_step(&partitions, &active, block_producer_ix);
+        let partitions: Vec<NodeSet> = partitions.into_iter().map(NodeSet::from).collect();
+        self.partition_step(&partitions, &active, block_producer_ix)
     }
 
-    fn check_same_partition(partitions: &[(usize, usize)], active: &[bool], a: ID, b: ID) -> bool {
-        if partitions.is_empty() {
-            return true;
-        }
-        let mut a_active = false;
-        let mut b_active = false;
-        for (r, (s, e)) in active.iter().zip(partitions) {
-            if *r && a >= *s && a < *e {
-                a_active = true;
-            }
-            if *r && b >= *s && b < *e {
-                b_active = true;
-            }
-            if a_active && b_active {
-                return true;
+    pub fn repair_partitions(&mut self, partitions: &[NodeSet], active: &[bool]) {
+        self.repair(&Partitions { partitions, active });
+    }
+
+    /// deliver the blocks that missed some nodes to the nodes the producer reaches now
+    pub fn repair(&mut self, connectivity: &dyn Connectivity) {
+        let (seed, slot) = (self.seed(), self.slot);
+        for (bp, block) in &self.partitioned_blocks {
+            for (id, n) in self.nodes.iter_mut().enumerate() {
+                if !n.blocks().contains_key(&block.0)
+                    && connectivity.reaches(seed, Kind::Block, *bp, id, slot)
+                {
+                    n.set_active_block(*block);
+                }
             }
         }
-        a_active && b_active
     }
 
-    pub fn repair_partitions(&mut self, partitions: &[(usize, usize)], active: &[bool]) {
-        for (bp, slot) in &self.partitioned_blocks {
-            for (id, n) in self.nodes.iter_mut().enumerate() {
-                if Self::check_same_partition(partitions, active, *bp, id) {
-                    n.set_active_block(*slot);
+    //votes are sent to the leader of the slot
+    fn vote(&mut self, connectivity: &dyn Connectivity, leader: ID) -> Result<(), SafetyViolation> {
+        //every node votes on its own view of the forks, the outcomes are collected
+        //in node order so the events and messages below don't depend on the threads
+        let active: Vec<bool> = self
+            .nodes
+            .iter()
+            .map(|n| connectivity.active(n.id))
+            .collect();
+        let mut strategies: Vec<Option<&mut Box<dyn VoterStrategy>>> =
+            self.nodes.iter().map(|_| None).collect();
+        for (id, strategy) in self.voters.iter_mut() {
+            strategies[*id] = Some(strategy);
+        }
+        let forks = &self.forks;
+        let outcomes: Vec<_> = self
+            .nodes
+            .par_iter_mut()
+            .zip(strategies.par_iter_mut())
+            .filter(|(n, _)| active[n.id])
+            .map(|(n, strategy)| {
+                let outcome = match strategy {
+                    Some(strategy) => strategy.vote(n, forks),
+                    None => n.vote(forks),
+                };
+                (n.id, outcome, n.root())
+            })
+            .collect();
+        let mut voted = 0;
+        let mut refused: HashMap<Refusal, usize> = HashMap::new();
+        //the other nodes' towers already moved, so their votes still go out before
+        //the first violation is returned
+        let mut violation = None;
+        for (node, outcome, root) in outcomes {
+            let outcome = match outcome {
+                Ok(outcome) => outcome,
+                Err(e) => {
+                    violation.get_or_insert(e);
+                    continue;
+                }
+            };
+            match outcome {
+                VoteOutcome::Voted(vote) => {
+                    let config = self.config();
+                    if config.votes.lost(config.seed, node, self.slot) {
+                        self.record(Event::VoteLost {
+                            slot: self.slot,
+                            node,
+                        });
+                    } else {
+                        let delay = config.latency.delay(config.seed, node, leader, self.slot);
+                        //the node voted on the version on its heaviest fork
+                        let votes = Payload::Votes {
+                            from: node,
+                            sent: self.slot,
+                            votes: self.nodes[node].votes(),
+                            id: self.nodes[node].heaviest_fork[&vote.slot],
+                        };
+                        self.send(delay, votes);
+                    }
+                    voted += 1;
+                    self.record(Event::VoteCast {
+                        node,
+                        vote,
+                        root: root.slot,
+                    });
+                }
+                VoteOutcome::Refused(reason) => {
+                    *refused.entry(reason.kind()).or_insert(0) += 1;
+                    if reason.kind() != Refusal::NotInSubcommittee {
+                        self.record(Event::VoteRefused { node, reason });
+                    }
                 }
+                VoteOutcome::Silent => (),
+            }
+        }
+        let mut refused: Vec<_> = refused.into_iter().collect();
+        refused.sort_unstable();
+        self.record(Event::VoteTally {
+            slot: self.slot,
+            voted,
+            refused,
+        });
+        violation.map_or(Ok(()), Err)
+    }
+
+    //a message without delay is delivered right away
+    fn send(&mut self, delay: Slot, payload: Payload) {
+        if delay == 0 {
+            return self.deliver(payload);
+        }
+        self.seq += 1;
+        self.in_flight.push(Reverse(Message {
+            arrival: self.slot.saturating_add(delay),
+            seq: self.seq,
+            payload,
+        }));
+    }
+
+    fn deliver(&mut self, payload: Payload) {
+        match payload {
+            Payload::Block { to, slot, id } => self.nodes[to].set_active_block((slot, id)),
+            Payload::Votes {
+                from,
+                sent,
+                votes,
+                id,
+            } => {
+                let tx = VoteTx {
+                    from,
+                    sent,
+                    votes,
+                    id,
+                };
+                self.pool.add(tx, &self.forks.config.votes);
             }
         }
     }
 
-    fn vote(&mut self, partitions: &[(usize, usize)], active: &[bool]) {
-        for (r, (s, e)) in active.iter().zip(partitions) {
-            if *r {
-                self.nodes[*s..*e]
-                    //.par_iter_mut()
-                    .iter_mut()
-                    .for_each(|n| n.vote(&self.forks));
+    fn deliver_arrived(&mut self) {
+        while let Some(Reverse(m)) = self.in_flight.peek() {
+            if m.arrival > self.slot {
+                break;
             }
+            let Reverse(m) = self.in_flight.pop().unwrap();
+            self.deliver(m.payload);
         }
     }
 
+    pub fn config(&self) -> &SimConfig {
+        &self.forks.config
+    }
+
+    /// the scheduled producer of `slot`
+    pub fn leader(&mut self, slot: Slot) -> ID {
+        self.leaders.leader(slot)
+    }
+
+    pub fn node(&self, id: ID) -> &Node {
+        &self.nodes[id]
+    }
+
+    /// the last slot that was stepped
+    pub fn slot(&self) -> Slot {
+        self.slot
+    }
+
+    /// the master seed every random choice in the run is derived from
+    pub fn seed(&self) -> u64 {
+        self.config().seed
+    }
+
+    /// randomness for a driver of the network, `index` picks an independent rng
+    pub fn rng(&self, index: u64) -> ChaCha8Rng {
+        seed::rng(self.seed(), Stream::Scenario, index)
+    }
+
     pub fn lowest_root(&self) -> Vote {
         self.forks.lowest_root
     }
 }
+
+#[test]
+fn test_configs_in_same_process() {
+    let mut small = SimConfig::new(16);
+    small.depth = 8;
+    small.threshold = 4;
+    small.subcommittee_size = 16;
+    let mut large = SimConfig::new(64);
+    large.subcommittee_size = 32;
+    let mut networks = [Network::new(small), Network::new(large)];
+    for _ in 0..128 {
+        for network in networks.iter_mut() {
+            network.step(1).unwrap();
+        }
+    }
+    for network in &networks {
+        assert!(network.lowest_root().slot > 64);
+        assert_eq!(network.lowest_root().lockout, 1 << network.config().depth);
+    }
+}
+
+#[test]
+fn test_event_stream() {
+    use crate::events::MemorySink;
+    let mut config = SimConfig::new(16);
+    config.subcommittee_size = 16;
+    let mut network = Network::new(config);
+    let sink = MemorySink::default();
+    network.set_sink(Box::new(sink.clone()));
+    for _ in 0..64 {
+        network.step(1).unwrap();
+    }
+    let events = sink.events();
+    let blocks = events
+        .iter()
+        .filter(|e| matches!(e, Event::BlockApplied { .. }))
+        .count();
+    assert_eq!(blocks, 64);
+    let last_root = events.iter().rev().find_map(|e| match e {
+        Event::RootAdvanced { to, .. } => Some(*to),
+        _ => None,
+    });
+    assert_eq!(last_root, Some(network.lowest_root().slot));
+    assert!(events.iter().any(|e| matches!(e, Event::VoteCast { .. })));
+}
+
+#[test]
+fn test_seed_reproduces_run() {
+    use crate::events::MemorySink;
+    let run = |seed| {
+        let mut config = SimConfig::new(32);
+        config.subcommittee_size = 16;
+        config.seed = seed;
+        let mut network = Network::new(config);
+        let sink = MemorySink::default();
+        network.set_sink(Box::new(sink.clone()));
+        for _ in 0..64 {
+            network.step(2).unwrap();
+        }
+        sink.events()
+    };
+    assert_eq!(run(3), run(3));
+    assert_ne!(run(3), run(4));
+}
+
+#[test]
+fn test_parallel_votes_are_deterministic() {
+    use crate::events::MemorySink;
+    let run = |threads| {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .unwrap();
+        pool.install(|| {
+            let mut config = SimConfig::new(64);
+            config.subcommittee_size = 32;
+            let mut network = Network::new(config);
+            network.set_voters(&[(VoterKind::ThresholdIgnoring, 0.1)]);
+            let sink = MemorySink::default();
+            network.set_sink(Box::new(sink.clone()));
+            for _ in 0..64 {
+                network.step(2).unwrap();
+            }
+            (sink.events(), network.snapshot(&[]))
+        })
+    };
+    assert_eq!(run(1), run(4));
+}
+
+#[test]
+fn test_incremental_fork_weights() {
+    let mut config = SimConfig::new(64);
+    config.subcommittee_size = 32;
+    config.seed = 5;
+    let mut network = Network::new(config);
+    network.set_voters(&[(VoterKind::LockoutViolating, 0.1)]);
+    //partitions grow competing forks until they are pruned by the root
+    for partitions in [3, 1, 2, 1] {
+        for _ in 0..48 {
+            network.step(partitions).unwrap();
+            let incremental = network.forks.primary_fork_weights.clone();
+            network.forks.build_fork_weights();
+            assert_eq!(incremental, network.forks.primary_fork_weights);
+        }
+    }
+    assert!(network.lowest_root().slot > 64);
+}
+
+#[test]
+fn test_step_after_safety_violation() {
+    use crate::bank::Block;
+    use crate::forks::Forks;
+    //includes a vote on a slot that isn't in the fork once, then produces honestly
+    struct Forged(Slot);
+    impl ProducerStrategy for Forged {
+        fn produce(
+            &mut self,
+            producer: &Node,
+            slot: Slot,
+            votes: Vec<VoteTx>,
+            _reached: &[ID],
+            _forks: &Forks,
+        ) -> Vec<(Block, Delivery)> {
+            let mut block = producer.make_block(slot, votes);
+            if slot == self.0 {
+                block
+                    .votes
+                    .push((producer.id, vec![Vote::new(slot + 1000)]));
+            }
+            vec![(block, Delivery::All)]
+        }
+    }
+    let mut config = SimConfig::new(16);
+    config.subcommittee_size = 16;
+    config.leader_window = 1;
+    let mut network = Network::new(config);
+    for id in 0..16 {
+        network.set_producer(id, Box::new(Forged(20)));
+    }
+    let mut violations = vec![];
+    for _ in 0..96 {
+        if let Err(violation) = network.step(1) {
+            violations.push((network.slot(), violation));
+        }
+        let incremental = network.forks.primary_fork_weights.clone();
+        network.forks.build_fork_weights();
+        assert_eq!(incremental, network.forks.primary_fork_weights);
+    }
+    assert!(
+        matches!(
+            violations[..],
+            [(20, SafetyViolation::VoteNotInFork { slot: 20, .. })]
+        ),
+        "{:?}",
+        violations
+    );
+    //the rejected block left nothing behind and the network kept rooting
+    assert!(network.forks.fork_map.keys().all(|k| k.0 != 20));
+    assert!(network
+        .forks
+        .fork_map
+        .values()
+        .all(|b| b.children.iter().all(|c| c.0 != 20)));
+    assert!(network.lowest_root().slot > 32);
+}
+
+#[test]
+fn test_votes_after_vote_violation() {
+    use crate::events::MemorySink;
+    use crate::forks::Forks;
+    use crate::voter::VoterStrategy;
+    //votes honestly, but reports a violation instead of its vote on `self.0`
+    struct Broken(Slot);
+    impl VoterStrategy for Broken {
+        fn vote(&mut self, node: &mut Node, forks: &Forks) -> Result<VoteOutcome, SafetyViolation> {
+            match node.vote(forks)? {
+                VoteOutcome::Voted(vote) if vote.slot == self.0 => {
+                    Err(SafetyViolation::RootNotInHeaviestFork {
+                        node: node.id,
+                        root: node.root().slot,
+                        heaviest_fork: vec![],
+                    })
+                }
+                outcome => Ok(outcome),
+            }
+        }
+    }
+    //the same run with and without the violation, the other nodes vote the same way
+    let run = |broken: bool| {
+        let mut config = SimConfig::new(16);
+        config.subcommittee_size = 16;
+        let mut network = Network::new(config);
+        if broken {
+            network.set_voter(0, Box::new(Broken(8)));
+        }
+        let sink = MemorySink::default();
+        network.set_sink(Box::new(sink.clone()));
+        let mut violations = vec![];
+        for _ in 0..9 {
+            if let Err(violation) = network.step(1) {
+                violations.push(violation);
+            }
+        }
+        let cast: Vec<_> = sink
+            .events()
+            .into_iter()
+            .filter(|e| matches!(e, Event::VoteCast { node, .. } if *node != 0))
+            .collect();
+        (network, violations, cast)
+    };
+    let (_, none, honest_cast) = run(false);
+    let (broken, violations, broken_cast) = run(true);
+    assert!(none.is_empty());
+    assert!(
+        matches!(
+            violations[..],
+            [SafetyViolation::RootNotInHeaviestFork { node: 0, .. }]
+        ),
+        "{:?}",
+        violations
+    );
+    assert_eq!(honest_cast, broken_cast);
+    //and their votes on 8 land in the next block
+    let mut broken = broken;
+    broken.step(1).unwrap();
+    let bank = &broken.forks.fork_map[&broken.forks.key(10)];
+    for e in &broken_cast {
+        if let Event::VoteCast { node, vote, .. } = e {
+            assert!(bank.nodes[*node].latest_vote().unwrap().slot >= vote.slot);
+        }
+    }
+}
+
+#[test]
+fn test_snapshot_restore() {
+    let mut config = SimConfig::new(32);
+    config.subcommittee_size = 16;
+    config.seed = 9;
+    let mut straight = Network::new(config.clone());
+    let mut resumed = Network::new(config);
+    for _ in 0..64 {
+        straight.step(2).unwrap();
+        resumed.step(2).unwrap();
+    }
+    let snapshot = resumed.snapshot(&[7, 8]);
+    let (mut resumed, driver) = Network::from_snapshot(&snapshot).unwrap();
+    assert_eq!(driver, vec![7, 8]);
+    assert_eq!(resumed.snapshot(&[7, 8]), snapshot);
+    for _ in 0..64 {
+        straight.step(1).unwrap();
+        resumed.step(1).unwrap();
+    }
+    assert_eq!(resumed.snapshot(&[]), straight.snapshot(&[]));
+    assert!(resumed.lowest_root().slot > 64);
+
+    assert!(Network::from_snapshot(&snapshot[..snapshot.len() - 1]).is_err());
+}
+
+#[test]
+fn test_skipped_slots() {
+    use crate::availability::Availability;
+    let mut config = SimConfig::new(32);
+    config.subcommittee_size = 32;
+    config.availability = Availability::uniform(0.3);
+    let mut network = Network::new(config);
+    for _ in 0..256 {
+        network.step(1).unwrap();
+    }
+    let skipped = network.metrics.skipped;
+    assert!(skipped > 0);
+    assert_eq!(network.metrics.blocks + skipped, 256);
+    assert!(network.lowest_root().slot > 64);
+    //the live forks have gaps where leaders skipped
+    assert!(network
+        .forks
+        .fork_map
+        .values()
+        .any(|b| b.slot > b.parent + 1));
+}
+
+#[test]
+fn test_message_latency() {
+    use crate::latency::{Delay, Latency};
+    let mut config = SimConfig::new(32);
+    config.subcommittee_size = 32;
+    config.latency = Latency::uniform(Delay::Fixed(1));
+    let mut network = Network::new(config.clone());
+    network.step(1).unwrap();
+    let leader = network.leader(1);
+    //only the producer has block 1 until the next slot
+    for n in &network.nodes {
+        assert_eq!(n.blocks().contains_key(&1), n.id == leader);
+    }
+    network.step(1).unwrap();
+    assert!(network.nodes.iter().all(|n| n.blocks().contains_key(&1)));
+    //the votes cast in slot 2 are still in flight when block 2 is made
+    let landed = |network: &Network, slot: Slot| {
+        network.forks.fork_map[&network.forks.key(slot)]
+            .nodes
+            .iter()
+            .filter(|t| !t.votes.is_empty())
+            .count()
+    };
+    assert!(landed(&network, 2) <= 1);
+    network.step(1).unwrap();
+    assert!(landed(&network, 3) > 16);
+
+    //in flight messages are part of a snapshot
+    config.latency = Latency::uniform(Delay::Uniform { min: 0, max: 1 });
+    let mut straight = Network::new(config);
+    for _ in 0..64 {
+        straight.step(1).unwrap();
+    }
+    assert!(!straight.in_flight.is_empty());
+    let (mut resumed, _) = Network::from_snapshot(&straight.snapshot(&[])).unwrap();
+    for _ in 0..64 {
+        straight.step(1).unwrap();
+        resumed.step(1).unwrap();
+    }
+    assert_eq!(resumed.snapshot(&[]), straight.snapshot(&[]));
+    assert!(straight.lowest_root().slot > 0);
+}
+
+#[test]
+fn test_vote_inclusion_policy() {
+    use crate::latency::{Delay, Latency};
+    use crate::mempool::VotePolicy;
+    let network = |opts: &[&str], delay| {
+        let mut config = SimConfig::new(32);
+        config.subcommittee_size = 16;
+        config.latency = Latency::uniform(delay);
+        config.votes = VotePolicy::parse(opts.iter().copied()).unwrap();
+        Network::new(config)
+    };
+    let run = |mut network: Network, slots| {
+        for _ in 0..slots {
+            network.step(1).unwrap();
+        }
+        network
+    };
+    let base = run(network(&[], Delay::Fixed(0)), 192);
+    let lossy = run(network(&["loss=0.3"], Delay::Fixed(0)), 192);
+    assert!(lossy.metrics.votes_lost > 0);
+    assert!(lossy.lowest_root().slot > 0);
+    //few votes per block land late and root slower
+    let capped = run(network(&["cap=4", "once"], Delay::Fixed(0)), 192);
+    assert!(capped.lowest_root().slot < base.lowest_root().slot);
+    assert!(capped.metrics.vote_delay.mean() > base.metrics.vote_delay.mean());
+    //only the leader's own votes arrive before they are too old
+    let stale = run(network(&["recent", "max_age=0"], Delay::Fixed(1)), 192);
+    assert!(stale.metrics.votes_stale > 0);
+    assert_eq!(stale.metrics.vote_delay.max(), 0);
+    assert!(stale.lowest_root().slot < base.lowest_root().slot);
+
+    //the pending transactions are part of the snapshot
+    let reordered = run(network(&["once"], Delay::Uniform { min: 0, max: 3 }), 96);
+    let (restored, _) = Network::from_snapshot(&reordered.snapshot(&[])).unwrap();
+    let (reordered, restored) = (run(reordered, 32), run(restored, 32));
+    assert!(reordered.metrics.vote_delay.max() > 0);
+    assert_eq!(reordered.snapshot(&[]), restored.snapshot(&[]));
+}
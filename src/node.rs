@@ -1,97 +1,255 @@
-use crate::bank::{Bank, Block, ID, NUM_NODES};
-use crate::forks::Forks;
-use crate::tower::{Slot, Tower, Vote};
+use crate::bank::{Bank, BankKey, Block, ID};
+use crate::config::SimConfig;
+use crate::forks::{Fork, Forks};
+use crate::mempool::VoteTx;
+use crate::safety::{sorted, SafetyViolation};
+use crate::snapshot::{Reader, SnapshotError, Writer};
+use crate::stake::Stake;
+use crate::tower::{Slot, Tower, TowerError, Vote};
 use std::collections::HashMap;
-use std::collections::HashSet;
+use std::sync::Arc;
 
+//default threshold depth, see `SimConfig::threshold`
 pub const THRESHOLD: usize = 6;
 
+#[derive(Clone, Debug, PartialEq)]
+pub enum VoteOutcome {
+    Voted(Vote),
+    Refused(Reason),
+    //a crashed or silent node, see `voter`
+    Silent,
+}
+
+/// The checks `Node::vote_with` runs before voting, honest nodes run all of them.
+/// Byzantine voters skip some, see `voter`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Checks {
+    //votes still locked out on another fork; skipping it also drops those votes from the tower
+    pub lockout: bool,
+    pub threshold: bool,
+    //the switching proof of `optimistic_conf_check`
+    pub switching: bool,
+}
+
+impl Checks {
+    pub const ALL: Checks = Checks {
+        lockout: true,
+        threshold: true,
+        switching: true,
+    };
+}
+
+/// Why a node didn't vote on its heaviest slot, `slot` is the heaviest slot.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Reason {
+    //neither in the primary nor the secondary of the heaviest bank
+    NotInSubcommittee {
+        slot: Slot,
+    },
+    //the heaviest slot isn't newer than the latest vote or root
+    AlreadyVoted {
+        slot: Slot,
+        latest: Slot,
+    },
+    //a vote that is still locked out isn't on the heaviest fork
+    LockedOut {
+        slot: Slot,
+        locked: Vote,
+    },
+    //the proposed lockout on `vote` doesn't have more than `required` primary stake
+    Threshold {
+        slot: Slot,
+        vote: Vote,
+        stake: Stake,
+        required: Stake,
+    },
+    //switching away from `last_vote` without more than `required` stake on other forks
+    OptimisticConfirmation {
+        slot: Slot,
+        last_vote: Slot,
+        stake: Stake,
+        required: Stake,
+    },
+}
+
+/// `Reason` without the details, for counting
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Refusal {
+    NotInSubcommittee,
+    AlreadyVoted,
+    LockedOut,
+    Threshold,
+    OptimisticConfirmation,
+}
+
+impl Refusal {
+    pub const ALL: [Refusal; 5] = [
+        Refusal::NotInSubcommittee,
+        Refusal::AlreadyVoted,
+        Refusal::LockedOut,
+        Refusal::Threshold,
+        Refusal::OptimisticConfirmation,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Refusal::NotInSubcommittee => "not_in_subcommittee",
+            Refusal::AlreadyVoted => "already_voted",
+            Refusal::LockedOut => "locked_out",
+            Refusal::Threshold => "threshold",
+            Refusal::OptimisticConfirmation => "optimistic_confirmation",
+        }
+    }
+}
+
+impl Reason {
+    pub fn slot(&self) -> Slot {
+        match self {
+            Reason::NotInSubcommittee { slot }
+            | Reason::AlreadyVoted { slot, .. }
+            | Reason::LockedOut { slot, .. }
+            | Reason::Threshold { slot, .. }
+            | Reason::OptimisticConfirmation { slot, .. } => *slot,
+        }
+    }
+
+    pub fn kind(&self) -> Refusal {
+        match self {
+            Reason::NotInSubcommittee { .. } => Refusal::NotInSubcommittee,
+            Reason::AlreadyVoted { .. } => Refusal::AlreadyVoted,
+            Reason::LockedOut { .. } => Refusal::LockedOut,
+            Reason::Threshold { .. } => Refusal::Threshold,
+            Reason::OptimisticConfirmation { .. } => Refusal::OptimisticConfirmation,
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct Node {
     pub id: ID,
-    //local view of the bank forks
-    blocks: HashSet<Slot>,
+    //local view of the bank forks, the version of each slot the node received
+    blocks: HashMap<Slot, u64>,
     tower: Tower,
-    pub heaviest_fork: HashSet<Slot>,
+    pub heaviest_fork: Arc<Fork>,
 }
 
 impl Node {
-    pub fn zero(id: ID) -> Self {
-        let mut blocks = HashSet::new();
-        blocks.insert(0);
-        let mut set = HashSet::new();
-        set.insert(0);
+    pub fn zero(id: ID, config: &SimConfig) -> Self {
         Node {
             id,
-            blocks,
-            tower: Tower::default(),
-            heaviest_fork: set,
+            blocks: HashMap::from([(0, 0)]),
+            tower: Tower::new(config.depth),
+            heaviest_fork: Arc::new(HashMap::from([(0, 0)])),
         }
     }
 
-    pub fn set_active_block(&mut self, slot: Slot) {
-        self.blocks.insert(slot);
+    pub fn encode(&self, w: &mut Writer) {
+        w.usize(self.id);
+        w.map(&self.blocks);
+        w.tower(&self.tower);
+        w.map(&self.heaviest_fork);
+    }
+
+    pub fn decode(r: &mut Reader) -> Result<Self, SnapshotError> {
+        Ok(Node {
+            id: r.usize()?,
+            blocks: r.map()?,
+            tower: r.tower()?,
+            heaviest_fork: Arc::new(r.map()?),
+        })
+    }
+
+    /// the node received the bank `key`, it keeps the first version of a slot it receives
+    pub fn set_active_block(&mut self, key: BankKey) {
+        self.blocks.entry(key.0).or_insert(key.1);
         if self.blocks.len() > 1024 {
             self.gc();
         }
     }
 
     fn gc(&mut self) {
-        self.blocks.retain(|x| *x >= self.tower.root.slot);
+        self.blocks.retain(|x, _| *x >= self.tower.root.slot);
+    }
+
+    //the heaviest fork has `slot`, and it's the version the node received if it has one
+    fn on_heaviest_fork(&self, slot: Slot) -> bool {
+        self.heaviest_fork
+            .get(&slot)
+            .is_some_and(|id| self.blocks.get(&slot).is_none_or(|b| b == id))
     }
 
-    fn threshold_check(&self, tower: &Tower, fork_map: &HashMap<Slot, Bank>) -> bool {
+    fn threshold_check(&self, tower: &Tower, bank: &Bank) -> Result<(), Reason> {
         let vote = tower.votes.front().unwrap();
-        let bank = fork_map.get(&vote.slot).unwrap();
         //check if the bank lockouts are increased
-        let proposed_lockouts = bank.nodes[self.id].get_incrased_lockouts(1 << THRESHOLD, tower);
+        let proposed_lockouts =
+            bank.nodes[self.id].get_incrased_lockouts(1 << bank.config.threshold, tower);
         if proposed_lockouts.is_empty() {
-            return true;
+            return Ok(());
         }
+        let mut proposed_lockouts: Vec<_> = proposed_lockouts.into_iter().collect();
+        proposed_lockouts.sort_unstable();
         for (slot, lockout) in proposed_lockouts {
             let v = Vote { slot, lockout };
             if !bank.primary_threshold_slot(&v) {
-                if self.id < 4 {
-                    println!("{} {} threshold check failed {:?}", self.id, bank.slot, v);
-                }
-                return false;
+                return Err(Reason::Threshold {
+                    slot: vote.slot,
+                    vote: v,
+                    stake: bank.primary_calc_threshold_slot(1 << 4, &v),
+                    required: bank.primary_threshold_stake(),
+                });
             }
         }
-        true
+        Ok(())
     }
 
-    fn optimistic_conf_check(&self, fork_weights: &HashMap<Slot, usize>, forks: &Forks) -> bool {
+    fn optimistic_conf_check(
+        &self,
+        heaviest_slot: Slot,
+        fork_weights: &HashMap<BankKey, Stake>,
+        forks: &Forks,
+    ) -> Result<(), Reason> {
         // no votes left in tower
         if self.tower.votes.front().is_none() {
-            return true;
+            return Ok(());
         }
         let last_vote = self.tower.votes.front().unwrap();
         // if the last vote is a decendant of the new fork
         // no switching proof is necessary
-        if self.heaviest_fork.contains(&last_vote.slot) {
-            return true;
+        if self.on_heaviest_fork(last_vote.slot) {
+            return Ok(());
         }
-        //all the recent forks but those decending from the last vote must have > 1/3 votes
+        //the node voted on the version it received
+        let last_key = (last_vote.slot, self.blocks[&last_vote.slot]);
+        //all the recent forks but those decending from the last vote must have > 1/3 of the stake
         let mut total = 0;
-        let last_vote_fork = forks.compute_fork(last_vote.slot);
-        for (slot, stake) in fork_weights {
-            if self.blocks.get(slot).is_none() {
+        for (key, stake) in fork_weights {
+            if self.blocks.get(&key.0) != Some(&key.1) {
                 continue;
             }
-            if *slot <= last_vote.slot {
+            if key.0 <= last_vote.slot {
                 //slot is older than last vote
                 continue;
             }
-            if last_vote_fork.contains(&slot) {
+            if forks.ancestry.is_ancestor(*key, last_key) {
                 //slot is a parent of the last voted fork
                 continue;
             }
-            let fork = forks.compute_fork(*slot);
-            if !fork.contains(&last_vote.slot) {
+            if !forks.ancestry.is_ancestor(last_key, *key) {
                 //slot is not a child of the last voted fork
                 total += stake;
             }
         }
-        total > NUM_NODES / 3
+        let required = forks.config.stakes.total() / 3;
+        if total > required {
+            Ok(())
+        } else {
+            Err(Reason::OptimisticConfirmation {
+                slot: heaviest_slot,
+                last_vote: last_vote.slot,
+                stake: total,
+                required,
+            })
+        }
     }
     pub fn votes(&self) -> Vec<Vote> {
         let mut votes = self.tower.votes();
@@ -100,77 +258,138 @@ impl Node {
         }
         votes
     }
-    pub fn make_block(&self, slot: Slot, votes: Vec<(ID, Vec<Vote>)>) -> Block {
-        let heaviest_slot = *self.heaviest_fork.iter().max().unwrap();
-        assert!(slot > heaviest_slot);
+    pub fn make_block(&self, slot: Slot, votes: Vec<VoteTx>) -> Block {
+        let heaviest = self.heaviest_fork.iter().map(|(s, id)| (*s, *id)).max();
+        self.make_block_on(heaviest.unwrap(), &self.heaviest_fork, slot, votes)
+    }
+
+    //build on `parent` whose fork is `fork`, only votes for that version of the fork are included
+    pub fn make_block_on(
+        &self,
+        parent: BankKey,
+        fork: &Fork,
+        slot: Slot,
+        votes: Vec<VoteTx>,
+    ) -> Block {
+        assert!(slot > parent.0);
         let votes: Vec<_> = votes
             .into_iter()
-            .filter(|(_, votes)| {
-                votes.last().is_some() && self.heaviest_fork.contains(&votes.last().unwrap().slot)
+            .filter(|tx| {
+                tx.votes
+                    .last()
+                    .is_some_and(|v| fork.get(&v.slot) == Some(&tx.id))
             })
+            .map(|tx| (tx.from, tx.votes))
             .collect();
         Block {
             slot,
-            parent: heaviest_slot,
+            parent: parent.0,
+            parent_id: parent.1,
+            producer: self.id,
             votes,
         }
     }
 
+    //local view of the bank forks
+    pub fn blocks(&self) -> &HashMap<Slot, u64> {
+        &self.blocks
+    }
+
     //the latest vote in tower is from the heaviest fork
     //the second to last vote that is still live in tower
     //must be in the heaviest fork, which is the same fork
     //that generated the vote
-    pub fn lockout_check(&self, tower: &Tower) -> bool {
-        let min = *self.heaviest_fork.iter().min().unwrap();
-        if tower.votes.len() > 0 {
-            for e in &tower.votes {
-                if e.slot < min {
-                    continue;
-                }
-                if !self.heaviest_fork.contains(&e.slot) {
-                    return false;
-                }
+    //returns the first vote that is locked out from the heaviest fork
+    pub fn lockout_check(&self, tower: &Tower) -> Result<(), Vote> {
+        let min = *self.heaviest_fork.keys().min().unwrap();
+        for e in &tower.votes {
+            if e.slot < min {
+                continue;
+            }
+            if !self.on_heaviest_fork(e.slot) {
+                return Err(*e);
             }
-            true
-        } else {
-            let rv = self.heaviest_fork.contains(&tower.root.slot) || tower.root.slot < min;
-            assert!(
-                rv,
-                "heaviest fork doesn't contain root {} {:?}",
-                tower.root.slot, self.heaviest_fork
-            );
-            rv
         }
+        Ok(())
     }
 
-    pub fn vote(&mut self, forks: &Forks) {
-        //filter out for blocks visibile to this nodes partition
-        let primary_weights: HashMap<Slot, usize> = forks
+    //with no votes left the root itself must be in the heaviest fork
+    fn root_check(&self, tower: &Tower) -> Result<(), SafetyViolation> {
+        let min = *self.heaviest_fork.keys().min().unwrap();
+        if tower.votes.is_empty()
+            && !self.on_heaviest_fork(tower.root.slot)
+            && tower.root.slot >= min
+        {
+            return Err(SafetyViolation::RootNotInHeaviestFork {
+                node: self.id,
+                root: tower.root.slot,
+                heaviest_fork: sorted(self.heaviest_fork.keys()),
+            });
+        }
+        Ok(())
+    }
+
+    pub fn vote(&mut self, forks: &Forks) -> Result<VoteOutcome, SafetyViolation> {
+        self.vote_with(forks, Checks::ALL)
+    }
+
+    /// update the heaviest fork without voting
+    pub fn observe(&mut self, forks: &Forks) -> Result<(), SafetyViolation> {
+        self.select_fork(forks).map(|_| ())
+    }
+
+    //sets the heaviest fork, returns its bank and the visible primary weights
+    fn select_fork(
+        &mut self,
+        forks: &Forks,
+    ) -> Result<(BankKey, HashMap<BankKey, Stake>), SafetyViolation> {
+        //filter out for the versions of blocks visibile to this node
+        let primary_weights: HashMap<BankKey, Stake> = forks
             .primary_fork_weights
             .iter()
-            .filter(|(x, _)| self.blocks.contains(x))
+            .filter(|(x, _)| self.blocks.get(&x.0) == Some(&x.1))
             .map(|(x, y)| (*x, *y))
             .collect();
-        //compute the heaviest slot
-        let heaviest_slot = primary_weights
+        //compute the heaviest bank, a node that doesn't see any block since
+        //the lowest root was cut off while it advanced and restarts from it
+        let heaviest = primary_weights
             .iter()
             .map(|(x, y)| (y, x))
             .max()
             .map(|(_, y)| *y)
-            .unwrap_or(0);
-        //recursively find the fork for the heaviest slot
-        let heaviest_fork = forks.compute_fork(heaviest_slot);
-        assert!(heaviest_fork.contains(&forks.lowest_root.slot));
+            .unwrap_or(forks.root());
+        //recursively find the fork for the heaviest bank
+        let heaviest_fork = forks.compute_fork(heaviest);
+        let root = forks.root();
+        if heaviest_fork.get(&root.0) != Some(&root.1) {
+            return Err(SafetyViolation::RootNotInHeaviestFork {
+                node: self.id,
+                root: forks.lowest_root.slot,
+                heaviest_fork: sorted(heaviest_fork.keys()),
+            });
+        }
         self.heaviest_fork = heaviest_fork;
+        Ok((heaviest, primary_weights))
+    }
+
+    pub fn vote_with(
+        &mut self,
+        forks: &Forks,
+        checks: Checks,
+    ) -> Result<VoteOutcome, SafetyViolation> {
+        let (heaviest, primary_weights) = self.select_fork(forks)?;
+        let heaviest_slot = heaviest.0;
         //grab the bank that this is voting on, and simulate the
         //votes applying to the forks tower state
-        let bank = forks.fork_map.get(&heaviest_slot).unwrap();
+        let bank = &forks.fork_map[&heaviest];
 
         //compute the simulated result against the bank state
         let mut result = bank.nodes[self.id].clone();
 
         if !bank.check_subcommittee(self.id) {
-            return;
+            return Ok(VoteOutcome::Refused(Reason::NotInSubcommittee {
+                slot: heaviest_slot,
+            }));
         }
         //simulate the vote
         let mut tower = self.tower.clone();
@@ -179,20 +398,28 @@ impl Node {
             lockout: 2,
         };
         //apply this vote and expire all the old votes
-        if tower.apply(&vote).is_err() {
-            //already voted
-            return;
-        }
-        //check if the lockouts aren't violated
-        //remaining votes in tower should be in the heaviest fork
-        if !self.lockout_check(&tower) {
-            if self.id < 4 {
-                println!(
-                    "{} recent vote is locked out from the heaviest fork {:?}",
-                    self.id, tower.votes[1]
-                );
+        if let Err(TowerError::VoteTooOld { latest }) = tower.apply(&vote) {
+            return Ok(VoteOutcome::Refused(Reason::AlreadyVoted {
+                slot: heaviest_slot,
+                latest,
+            }));
+        }
+        if checks.lockout {
+            self.root_check(&tower)?;
+            //check if the lockouts aren't violated
+            //remaining votes in tower should be in the heaviest fork
+            if let Err(locked) = self.lockout_check(&tower) {
+                return Ok(VoteOutcome::Refused(Reason::LockedOut {
+                    slot: heaviest_slot,
+                    locked,
+                }));
             }
-            return;
+        } else {
+            //forget the votes on other forks, they stay visible in the blocks they landed in
+            let min = *self.heaviest_fork.keys().min().unwrap();
+            tower
+                .votes
+                .retain(|v| v.slot < min || self.on_heaviest_fork(v.slot));
         }
         let proposed = tower.votes();
         assert!(proposed[0].slot <= proposed.last().unwrap().slot);
@@ -203,33 +430,19 @@ impl Node {
         //check if the simulated result exceeds the thershold check
         //if the simulation increases the lockout, the bank should have
         //2/3+ nodes voting on the locked out slot
-        if !self.threshold_check(&result, &forks.fork_map) {
-            if self.id < 4 {
-                println!("{} THRESHOLD CHECK FAILED", self.id);
-                for (v, t) in self.tower.votes.iter().zip(result.votes.iter()) {
-                    println!(
-                        "{} LOCKOUT {:?} {} {:?} {}",
-                        self.id,
-                        v,
-                        bank.primary_calc_threshold_slot(1, v),
-                        t,
-                        bank.primary_calc_threshold_slot(2, t)
-                    );
-                }
+        if checks.threshold {
+            if let Err(reason) = self.threshold_check(&result, bank) {
+                return Ok(VoteOutcome::Refused(reason));
             }
-            return;
         }
         //check if this node is switching forks. if its switching forks then
         //at least 1/3 of the nodes must be voting on forks that are not the last
         //vote's fork
-        if !self.optimistic_conf_check(&primary_weights, forks) {
-            if self.id < 4 {
-                println!("{} OC CHECK FAILED", self.id);
+        if checks.switching {
+            if let Err(reason) = self.optimistic_conf_check(heaviest_slot, &primary_weights, forks)
+            {
+                return Ok(VoteOutcome::Refused(reason));
             }
-            return;
-        }
-        if self.id < 4 {
-            println!("{} voting {:?} root: {:?}", self.id, vote, self.tower.root);
         }
         for v in 1..tower.votes.len() {
             let v = &tower.votes[v];
@@ -240,14 +453,60 @@ impl Node {
                 self.tower
             );
         }
-        if self.tower.root != tower.root {
-            if self.id < 4 {
-                println!(
-                    "{} updated root {:?} old root: {:?}",
-                    self.id, tower.root, self.tower.root
-                );
-            }
-        }
         self.tower = tower;
+        Ok(VoteOutcome::Voted(vote))
+    }
+
+    pub fn root(&self) -> Vote {
+        self.tower.root
+    }
+}
+
+#[cfg(test)]
+fn empty_block(slot: Slot, parent: Slot) -> Block {
+    Block {
+        slot,
+        parent,
+        parent_id: 0,
+        producer: 0,
+        votes: vec![],
+    }
+}
+
+#[test]
+fn test_vote_outcomes() {
+    use crate::stake::Stakes;
+    use std::sync::Arc;
+    let mut config = SimConfig::new(4);
+    //node 3 has no stake, so it is never sampled into the subcommittee
+    config.stakes = Stakes::new(vec![1, 1, 1, 0]);
+    config.subcommittee_size = 64;
+    let mut forks = Forks::new(Arc::new(config.clone()));
+    let mut node = Node::zero(0, &config);
+    let mut outsider = Node::zero(3, &config);
+    assert_eq!(
+        outsider.vote(&forks).unwrap(),
+        VoteOutcome::Refused(Reason::NotInSubcommittee { slot: 0 })
+    );
+    assert_eq!(
+        node.vote(&forks).unwrap(),
+        VoteOutcome::Refused(Reason::AlreadyVoted { slot: 0, latest: 0 })
+    );
+
+    // 0 -> 1
+    forks.apply(&empty_block(1, 0)).unwrap();
+    node.set_active_block((1, empty_block(1, 0).id()));
+    assert_eq!(node.vote(&forks).unwrap(), VoteOutcome::Voted(Vote::new(1)));
+    assert_eq!(
+        node.vote(&forks).unwrap(),
+        VoteOutcome::Refused(Reason::AlreadyVoted { slot: 1, latest: 1 })
+    );
+
+    // 0 -> 2, the vote on 1 never landed so 2 is just as heavy but newer
+    forks.apply(&empty_block(2, 0)).unwrap();
+    node.set_active_block((2, empty_block(2, 0).id()));
+    match node.vote(&forks).unwrap() {
+        VoteOutcome::Refused(Reason::LockedOut { slot: 2, locked }) => assert_eq!(locked.slot, 1),
+        outcome => panic!("unexpected outcome {:?}", outcome),
     }
 }
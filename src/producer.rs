@@ -0,0 +1,294 @@
+use crate::bank::{Block, ID};
+use crate::forks::Forks;
+use crate::mempool::VoteTx;
+use crate::node::Node;
+use crate::tower::Slot;
+use std::collections::HashSet;
+
+/// who a block is sent to, out of the nodes the producer can reach
+#[derive(Clone, Debug, PartialEq)]
+pub enum Delivery {
+    All,
+    Nodes(HashSet<ID>),
+}
+
+/// How a leader builds its blocks. `votes` are the towers the mempool picked for
+/// the block under the inclusion policy, `reached` are the nodes the block can be
+/// sent to, sorted by id. A strategy may return several blocks for the same slot,
+/// `Forks` applies each version by `Block::id` as a bank of its own and catches the
+/// producer equivocating. Nodes vote on the first version they receive.
+pub trait ProducerStrategy: Send {
+    fn produce(
+        &mut self,
+        producer: &Node,
+        slot: Slot,
+        votes: Vec<VoteTx>,
+        reached: &[ID],
+        forks: &Forks,
+    ) -> Vec<(Block, Delivery)>;
+}
+
+/// builds on the heaviest fork and includes every vote for it
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Honest;
+
+impl ProducerStrategy for Honest {
+    fn produce(
+        &mut self,
+        producer: &Node,
+        slot: Slot,
+        votes: Vec<VoteTx>,
+        _reached: &[ID],
+        _forks: &Forks,
+    ) -> Vec<(Block, Delivery)> {
+        vec![(producer.make_block(slot, votes), Delivery::All)]
+    }
+}
+
+/// Sends one version of the block to the lower half of the reachable nodes
+/// and a version without votes that skips the heaviest slot to the upper half.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Equivocate;
+
+impl ProducerStrategy for Equivocate {
+    fn produce(
+        &mut self,
+        producer: &Node,
+        slot: Slot,
+        votes: Vec<VoteTx>,
+        reached: &[ID],
+        forks: &Forks,
+    ) -> Vec<(Block, Delivery)> {
+        let (lower, upper) = reached.split_at(reached.len() / 2);
+        let block = producer.make_block(slot, votes);
+        //the other version conflicts with the first one's parent when it can
+        let parent = (block.parent, block.parent_id);
+        let parent = match forks.fork_map.get(&parent) {
+            Some(bank) if forks.fork_map.contains_key(&bank.parent_key()) => bank.parent_key(),
+            _ => parent,
+        };
+        let other = producer.make_block_on(parent, &forks.compute_fork(parent), slot, vec![]);
+        vec![
+            (block, Delivery::Nodes(lower.iter().cloned().collect())),
+            (other, Delivery::Nodes(upper.iter().cloned().collect())),
+        ]
+    }
+}
+
+/// Builds on the heaviest block the producer has seen that is not on its
+/// heaviest fork, or on the heaviest fork if there is no other fork.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MinorityFork;
+
+impl ProducerStrategy for MinorityFork {
+    fn produce(
+        &mut self,
+        producer: &Node,
+        slot: Slot,
+        votes: Vec<VoteTx>,
+        _reached: &[ID],
+        forks: &Forks,
+    ) -> Vec<(Block, Delivery)> {
+        let minority = forks
+            .primary_fork_weights
+            .iter()
+            .filter(|(k, _)| {
+                producer.blocks().get(&k.0) == Some(&k.1)
+                    && producer.heaviest_fork.get(&k.0) != Some(&k.1)
+            })
+            .map(|(k, w)| (*w, *k))
+            .max();
+        let block = match minority {
+            Some((_, parent)) => {
+                let fork = forks.compute_fork(parent);
+                producer.make_block_on(parent, &fork, slot, votes)
+            }
+            None => producer.make_block(slot, votes),
+        };
+        vec![(block, Delivery::All)]
+    }
+}
+
+/// builds on the heaviest fork but leaves out the votes of `censored` nodes
+#[derive(Clone, Debug, Default)]
+pub struct Censor {
+    pub censored: HashSet<ID>,
+}
+
+impl ProducerStrategy for Censor {
+    fn produce(
+        &mut self,
+        producer: &Node,
+        slot: Slot,
+        mut votes: Vec<VoteTx>,
+        _reached: &[ID],
+        _forks: &Forks,
+    ) -> Vec<(Block, Delivery)> {
+        votes.retain(|tx| !self.censored.contains(&tx.from));
+        vec![(producer.make_block(slot, votes), Delivery::All)]
+    }
+}
+
+#[test]
+fn test_equivocation_and_censorship() {
+    use crate::config::SimConfig;
+    use crate::events::{Event, MemorySink};
+    use crate::network::Network;
+    use crate::slashing::Evidence;
+    use std::sync::Arc;
+    let mut config = SimConfig::new(16);
+    config.subcommittee_size = 16;
+    let mut network = Network::new(config.clone());
+    for id in 0..4 {
+        network.set_producer(id, Box::new(Equivocate));
+    }
+    network.set_producer(4, Box::new(MinorityFork));
+    let sink = MemorySink::default();
+    network.set_sink(Box::new(sink.clone()));
+    for _ in 0..256 {
+        network.step(1).unwrap();
+    }
+    let duplicates: Vec<_> = sink
+        .events()
+        .into_iter()
+        .filter_map(|e| match e {
+            Event::DuplicateBlock {
+                slot,
+                producer,
+                id,
+                first_id,
+                parent,
+                first_parent,
+            } => Some((slot, producer, id != first_id, parent < first_parent)),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(duplicates.len() as u64, network.metrics.duplicates);
+    assert!(duplicates.len() > 16);
+    //every duplicate is a different version on a conflicting parent
+    assert!(duplicates.iter().all(|(_, _, id, parent)| *id && *parent));
+    //and is evidence against its producer
+    let equivocations = sink
+        .events()
+        .into_iter()
+        .filter(|e| {
+            matches!(
+                e,
+                Event::Slashable {
+                    evidence: Evidence::Equivocation { .. }
+                }
+            )
+        })
+        .count();
+    assert_eq!(equivocations, duplicates.len());
+    let offenders = &network.forks.detector.offenders;
+    assert!(duplicates.iter().all(|(_, p, _, _)| offenders.contains(p)));
+    assert!(network.lowest_root().slot > 64);
+
+    //both versions build on the heaviest fork, only the first carries the votes
+    struct Split;
+    impl ProducerStrategy for Split {
+        fn produce(
+            &mut self,
+            producer: &Node,
+            slot: Slot,
+            votes: Vec<VoteTx>,
+            reached: &[ID],
+            _forks: &Forks,
+        ) -> Vec<(Block, Delivery)> {
+            let (lower, upper) = reached.split_at(reached.len() / 2);
+            vec![
+                (
+                    producer.make_block(slot, votes),
+                    Delivery::Nodes(lower.iter().cloned().collect()),
+                ),
+                (
+                    producer.make_block(slot, vec![]),
+                    Delivery::Nodes(upper.iter().cloned().collect()),
+                ),
+            ]
+        }
+    }
+    let mut network = Network::new(config.clone());
+    let sink = MemorySink::default();
+    network.set_sink(Box::new(sink.clone()));
+    let slot = 8;
+    for _ in 1..slot {
+        network.step(1).unwrap();
+    }
+    let producer = network.leader(slot);
+    network.set_producer(producer, Box::new(Split));
+    network.step(1).unwrap();
+    network.set_producer(producer, Box::new(Honest));
+    network.step(1).unwrap();
+    let versions: HashSet<u64> = network
+        .forks
+        .fork_map
+        .keys()
+        .filter(|k| k.0 == slot)
+        .map(|k| k.1)
+        .collect();
+    assert_eq!(versions.len(), 2);
+    //each half voted on the version it received
+    let held = |id: ID| network.node(id).blocks()[&slot];
+    let voted: HashSet<u64> = sink
+        .events()
+        .into_iter()
+        .filter_map(|e| match e {
+            Event::VoteCast { node, vote, .. } if vote.slot == slot => Some(held(node)),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(voted, versions);
+    //the next block only carries the votes for the version it builds on
+    let next = network.forks.key(slot + 1);
+    let version = network.forks.version(next, slot).unwrap();
+    let bank = &network.forks.fork_map[&next];
+    let landed: Vec<ID> = (0..16)
+        .filter(|id| {
+            bank.nodes[*id]
+                .latest_vote()
+                .is_some_and(|v| v.slot == slot)
+        })
+        .collect();
+    assert!(!landed.is_empty());
+    assert!(landed.iter().all(|id| held(*id) == version));
+    //and the detector caught the producer
+    assert!(network.forks.detector.offenders.contains(&producer));
+    assert!(sink.events().into_iter().any(|e| matches!(
+        e,
+        Event::Slashable {
+            evidence: Evidence::Equivocation { slot: s, producer: p, .. }
+        } if s == slot && p == producer
+    )));
+    //the versions split the reached nodes even without votes to include
+    let forks = Forks::new(Arc::new(config.clone()));
+    let reached: Vec<ID> = (0..16).collect();
+    let sent: Vec<_> = Equivocate
+        .produce(&Node::zero(0, &config), 1, vec![], &reached, &forks)
+        .into_iter()
+        .flat_map(|(_, delivery)| match delivery {
+            Delivery::Nodes(ids) => ids,
+            Delivery::All => unreachable!(),
+        })
+        .collect();
+    assert_eq!(sent.len(), 16);
+    assert_eq!(sent.into_iter().collect::<HashSet<_>>().len(), 16);
+
+    let mut network = Network::new(config);
+    for id in 0..16 {
+        let censor = Censor {
+            censored: [15].into_iter().collect(),
+        };
+        network.set_producer(id, Box::new(censor));
+    }
+    for _ in 0..128 {
+        network.step(1).unwrap();
+    }
+    let latest = network.forks.fork_map.keys().max().unwrap();
+    let bank = &network.forks.fork_map[latest];
+    //the lowest root can stall on the censored node's root, the others still root
+    assert!(bank.nodes[15].votes.is_empty());
+    assert_eq!(bank.nodes[15].root.slot, 0);
+    assert!(bank.nodes[0].root.slot > 0);
+}
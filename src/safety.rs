@@ -0,0 +1,153 @@
+use crate::bank::ID;
+use crate::tower::Slot;
+use std::fmt;
+
+/// A broken safety invariant. The block or vote that broke it is left out and
+/// the rest of the slot is skipped, so the network can still be stepped.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SafetyViolation {
+    //the primary and secondary super roots in bank `slot` are on different forks,
+    //`fork` is the fork of the higher super root
+    Diverged {
+        slot: Slot,
+        lowest_root: Slot,
+        primary: Slot,
+        secondary: Slot,
+        fork: Vec<Slot>,
+    },
+    //a super root in bank `slot` is below the lowest root but was never rooted
+    UnrootedSuperRoot {
+        slot: Slot,
+        lowest_root: Slot,
+        super_root: Slot,
+    },
+    //the lowest root moved to a fork that doesn't contain the previous lowest root
+    RootNotDescendant {
+        slot: Slot,
+        old_root: Slot,
+        new_root: Slot,
+        fork: Vec<Slot>,
+    },
+    //an optimistically confirmed slot was dropped without being rooted
+    OcFailed {
+        slot: Slot,
+        oc_slot: Slot,
+        lowest_root: Slot,
+    },
+    //block `slot` contains a vote from `node` that isn't on the bank's fork
+    VoteNotInFork {
+        slot: Slot,
+        node: ID,
+        vote: Slot,
+        fork: Vec<Slot>,
+    },
+    //a node's heaviest fork doesn't contain its own root or the lowest root
+    RootNotInHeaviestFork {
+        node: ID,
+        root: Slot,
+        heaviest_fork: Vec<Slot>,
+    },
+}
+
+//sorted, for stable reports
+pub fn sorted<'a>(fork: impl IntoIterator<Item = &'a Slot>) -> Vec<Slot> {
+    let mut fork: Vec<_> = fork.into_iter().cloned().collect();
+    fork.sort_unstable();
+    fork
+}
+
+impl fmt::Display for SafetyViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SafetyViolation::Diverged {
+                slot,
+                lowest_root,
+                primary,
+                secondary,
+                fork,
+            } => write!(
+                f,
+                "bank {}: primary super root {} and secondary super root {} diverged above lowest root {}, fork {:?}",
+                slot, primary, secondary, lowest_root, fork
+            ),
+            SafetyViolation::UnrootedSuperRoot {
+                slot,
+                lowest_root,
+                super_root,
+            } => write!(
+                f,
+                "bank {}: super root {} is below lowest root {} but was never rooted",
+                slot, super_root, lowest_root
+            ),
+            SafetyViolation::RootNotDescendant {
+                slot,
+                old_root,
+                new_root,
+                fork,
+            } => write!(
+                f,
+                "bank {}: new lowest root {} doesn't descend from {}, fork {:?}",
+                slot, new_root, old_root, fork
+            ),
+            SafetyViolation::OcFailed {
+                slot,
+                oc_slot,
+                lowest_root,
+            } => write!(
+                f,
+                "slot {}: optimistically confirmed slot {} was dropped, lowest root {}",
+                slot, oc_slot, lowest_root
+            ),
+            SafetyViolation::VoteNotInFork {
+                slot,
+                node,
+                vote,
+                fork,
+            } => write!(
+                f,
+                "block {}: vote from node {} on slot {} is not in the bank's fork {:?}",
+                slot, node, vote, fork
+            ),
+            SafetyViolation::RootNotInHeaviestFork {
+                node,
+                root,
+                heaviest_fork,
+            } => write!(
+                f,
+                "node {}: heaviest fork doesn't contain root {}, fork {:?}",
+                node, root, heaviest_fork
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SafetyViolation {}
+
+#[test]
+fn test_vote_not_in_fork() {
+    use crate::bank::Block;
+    use crate::config::SimConfig;
+    use crate::forks::Forks;
+    use crate::tower::Vote;
+    use std::sync::Arc;
+    let mut forks = Forks::new(Arc::new(SimConfig::new(4)));
+    let block = |slot, parent, votes| Block {
+        slot,
+        parent,
+        parent_id: 0,
+        producer: 0,
+        votes,
+    };
+    forks.apply(&block(1, 0, vec![])).unwrap();
+    // 0 -> 2 carrying a vote for 1
+    let rv = forks.apply(&block(2, 0, vec![(0, vec![Vote::new(1)])]));
+    assert_eq!(
+        rv,
+        Err(SafetyViolation::VoteNotInFork {
+            slot: 2,
+            node: 0,
+            vote: 1,
+            fork: vec![0, 2],
+        })
+    );
+}
@@ -0,0 +1,784 @@
+//! Declarative partition schedules.
+//!
+//! A scenario is a text file with one directive per line, `#` starts a comment.
+//!
+//! ```text
+//! nodes 100                    # config overrides: nodes, depth, threshold,
+//! depth 16                     # subcommittee_size, subcommittee_epoch, seed,
+//! leader_window 4              # leader_window, leader_epoch, stake_weighted_leaders 0|1
+//! skip_rate 0.1                # leaders skip their slot with probability 0.1
+//! skip_rate 0.5 small          # except the nodes of `small`, or of a range like 66..100
+//! latency 0..=2                # blocks and votes take 0 to 2 slots to arrive
+//! latency 8 big small          # except from `big` to `small`
+//! votes cap=32 recent max_age=8  # vote inclusion, see `mempool::VotePolicy::parse`
+//! partition big 0..66          # named node ranges, [start, end)
+//! partition small 66..100
+//! partition odd 1,3,5,7..10    # or any set of nodes,
+//! partition committee primary  # the primary subcommittee,
+//! partition third stake=0.33   # random nodes with 33% of the stake, picked from the seed,
+//! partition others rest        # or the nodes in no other partition
+//! strategy equivocate 0..4     # leaders 0..4 send conflicting blocks to each half of the nodes,
+//! strategy minority_fork small # `small` builds on the heaviest competing fork,
+//! strategy censor big censor=small  # and `big` drops the votes of `small`
+//! voter lockout_violating 0..4 # nodes 0..4 vote ignoring their lockouts, see `voter::VoterKind`
+//! step 32                      # 32 slots of `Network::step(1)`
+//! step 64 partitions=3         # 64 slots of `Network::step(3)`
+//! run 16 active=big producer=big  # 16 slots with only `big` live, `big`'s first node produces
+//!                              # members of `primary` and `rest` partitions are looked up
+//!                              # at every run and repair
+//! run 4 active=big,small producer=70
+//! repair big,small             # deliver the partitioned blocks between big and small
+//! cut big 70..71               # node 70 stops hearing from `big`, links are one way
+//! link small big loss=0.2      # 20% of the messages from `small` to `big` are lost
+//! graph 32                     # 32 slots of `Network::graph_step` over the links so far
+//! heal                         # every node reaches every other node again
+//! mark                         # remember the current lowest root
+//! expect root >= 10            # lowest root is at least slot 10
+//! expect progress >= 1         # lowest root advanced at least 1 slot since the last mark
+//! ```
+use crate::bank::ID;
+use crate::config::SimConfig;
+use crate::connectivity::{Graph, NodeSet};
+use crate::latency::Delay;
+use crate::mempool::VotePolicy;
+use crate::network::Network;
+use crate::producer::{Censor, Equivocate, MinorityFork, ProducerStrategy};
+use crate::safety::SafetyViolation;
+use crate::stake::Stakes;
+use crate::tower::Slot;
+use crate::voter::VoterKind;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+pub struct Scenario {
+    pub config: SimConfig,
+    pub partitions: Vec<(String, Members)>,
+    pub producers: Vec<((ID, ID), Strategy)>,
+    pub voters: Vec<((ID, ID), VoterKind)>,
+    pub commands: Vec<(usize, Command)>,
+}
+
+/// who is in a partition
+#[derive(Clone, Debug, PartialEq)]
+pub enum Members {
+    Nodes(NodeSet),
+    //the primary subcommittee when the partition is used
+    Primary,
+    //random nodes holding this fraction of the stake
+    Stake(f64),
+    //the nodes in no other partition
+    Rest,
+}
+
+/// malicious producer strategies, see `producer`
+#[derive(Clone, Debug, PartialEq)]
+pub enum Strategy {
+    Equivocate,
+    MinorityFork,
+    Censor((ID, ID)),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    Step {
+        slots: usize,
+        partitions: usize,
+    },
+    Run {
+        slots: usize,
+        active: Vec<bool>,
+        producer: Producer,
+    },
+    Repair {
+        active: Vec<bool>,
+    },
+    //`None` cuts the link
+    Link {
+        from: (ID, ID),
+        to: (ID, ID),
+        loss: Option<f64>,
+    },
+    Heal,
+    Graph {
+        slots: usize,
+    },
+    Mark,
+    Expect(Expectation),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Producer {
+    Node(ID),
+    //the lowest member of the partition at this index
+    First(usize),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Expectation {
+    RootAtLeast(Slot),
+    ProgressAtLeast(Slot),
+}
+
+#[derive(Debug)]
+pub enum ScenarioError {
+    Io(io::Error),
+    Parse {
+        line: usize,
+        msg: String,
+    },
+    Failed {
+        line: usize,
+        expectation: Expectation,
+        actual: Slot,
+    },
+    Safety {
+        line: usize,
+        violation: SafetyViolation,
+    },
+    //the producer partition has no members
+    NoProducer {
+        line: usize,
+        partition: String,
+    },
+}
+
+impl fmt::Display for ScenarioError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScenarioError::Io(e) => write!(f, "{}", e),
+            ScenarioError::Parse { line, msg } => write!(f, "line {}: {}", line, msg),
+            ScenarioError::Failed {
+                line,
+                expectation,
+                actual,
+            } => write!(
+                f,
+                "line {}: expected {:?}, lowest root measured {}",
+                line, expectation, actual
+            ),
+            ScenarioError::Safety { line, violation } => {
+                write!(f, "line {}: safety violation: {}", line, violation)
+            }
+            ScenarioError::NoProducer { line, partition } => {
+                write!(f, "line {}: partition {} is empty", line, partition)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ScenarioError {}
+
+impl From<io::Error> for ScenarioError {
+    fn from(e: io::Error) -> Self {
+        ScenarioError::Io(e)
+    }
+}
+
+fn parse_err<T>(line: usize, msg: impl Into<String>) -> Result<T, ScenarioError> {
+    Err(ScenarioError::Parse {
+        line,
+        msg: msg.into(),
+    })
+}
+
+fn parse_num<T: std::str::FromStr>(line: usize, val: &str) -> Result<T, ScenarioError> {
+    match val.parse() {
+        Ok(v) => Ok(v),
+        Err(_) => parse_err(line, format!("expected a number, got {:?}", val)),
+    }
+}
+
+impl Scenario {
+    pub fn load(path: &Path, config: SimConfig) -> Result<Self, ScenarioError> {
+        let text = fs::read_to_string(path)?;
+        Self::parse(&text, config)
+    }
+
+    /// parse a scenario, `config` is used for anything the scenario doesn't override
+    pub fn parse(text: &str, mut config: SimConfig) -> Result<Self, ScenarioError> {
+        let mut partitions: Vec<(String, Members)> = vec![];
+        let mut commands = vec![];
+        let mut producers = vec![];
+        let mut voters = vec![];
+        let mut nodes = None;
+        for (i, line) in text.lines().enumerate() {
+            let line_no = i + 1;
+            let line = line.split('#').next().unwrap().trim();
+            let words: Vec<_> = line.split_whitespace().collect();
+            if words.is_empty() {
+                continue;
+            }
+            let arg = |ix: usize| match words.get(ix) {
+                Some(w) => Ok(*w),
+                None => parse_err(line_no, format!("{} is missing an argument", words[0])),
+            };
+            match words[0] {
+                "nodes" => nodes = Some(parse_num(line_no, arg(1)?)?),
+                "depth" => config.depth = parse_num(line_no, arg(1)?)?,
+                "threshold" => config.threshold = parse_num(line_no, arg(1)?)?,
+                "subcommittee_size" => config.subcommittee_size = parse_num(line_no, arg(1)?)?,
+                "subcommittee_epoch" => config.subcommittee_epoch = parse_num(line_no, arg(1)?)?,
+                "seed" => config.seed = parse_num(line_no, arg(1)?)?,
+                "leader_window" => config.leader_window = parse_num(line_no, arg(1)?)?,
+                "leader_epoch" => config.leader_epoch = parse_num(line_no, arg(1)?)?,
+                "stake_weighted_leaders" => {
+                    config.stake_weighted_leaders = parse_num::<u64>(line_no, arg(1)?)? != 0
+                }
+                "skip_rate" => {
+                    let rate: f64 = parse_num(line_no, arg(1)?)?;
+                    if !(0.0..=1.0).contains(&rate) {
+                        return parse_err(line_no, format!("invalid skip rate {}", rate));
+                    }
+                    match words.get(2) {
+                        None => config.availability.skip_rate = rate,
+                        Some(w) => {
+                            let range = Self::parse_nodes(line_no, &partitions, w)?;
+                            config.availability.set(range, rate);
+                        }
+                    }
+                }
+                "latency" => {
+                    let delay = match Delay::parse(arg(1)?) {
+                        Some(delay) => delay,
+                        None => return parse_err(line_no, format!("invalid delay {}", arg(1)?)),
+                    };
+                    match (words.get(2), words.get(3)) {
+                        (None, _) => config.latency.delay = delay,
+                        (Some(from), Some(to)) if words.len() == 4 => {
+                            let from = Self::parse_nodes(line_no, &partitions, from)?;
+                            let to = Self::parse_nodes(line_no, &partitions, to)?;
+                            config.latency.set(from, to, delay);
+                        }
+                        _ => return parse_err(line_no, "latency needs both from and to nodes"),
+                    }
+                }
+                "votes" => {
+                    config.votes = VotePolicy::parse(words[1..].iter().copied())
+                        .map_err(|msg| ScenarioError::Parse { line: line_no, msg })?;
+                }
+                "partition" => {
+                    let name = arg(1)?.to_string();
+                    if partitions.iter().any(|(n, _)| *n == name) {
+                        return parse_err(line_no, format!("duplicate partition {}", name));
+                    }
+                    let members = Self::parse_members(line_no, arg(2)?)?;
+                    if words.len() > 3 {
+                        return parse_err(line_no, "too many arguments");
+                    }
+                    partitions.push((name, members));
+                }
+                "strategy" => {
+                    let nodes = Self::parse_nodes(line_no, &partitions, arg(2)?)?;
+                    let strategy = match (arg(1)?, words.get(3)) {
+                        ("equivocate", None) => Strategy::Equivocate,
+                        ("minority_fork", None) => Strategy::MinorityFork,
+                        ("censor", Some(opt)) => match opt.strip_prefix("censor=") {
+                            Some(v) => {
+                                Strategy::Censor(Self::parse_nodes(line_no, &partitions, v)?)
+                            }
+                            None => return parse_err(line_no, format!("unknown option {}", opt)),
+                        },
+                        ("censor", None) => return parse_err(line_no, "censor needs censor="),
+                        (name, _) => {
+                            return parse_err(line_no, format!("unknown strategy {}", name))
+                        }
+                    };
+                    if words.len() > 4 {
+                        return parse_err(line_no, "too many arguments");
+                    }
+                    producers.push((nodes, strategy));
+                }
+                "voter" => {
+                    let kind = match VoterKind::parse(arg(1)?) {
+                        Some(kind) => kind,
+                        None => return parse_err(line_no, format!("unknown voter {}", arg(1)?)),
+                    };
+                    let nodes = Self::parse_nodes(line_no, &partitions, arg(2)?)?;
+                    if words.len() > 3 {
+                        return parse_err(line_no, "too many arguments");
+                    }
+                    voters.push((nodes, kind));
+                }
+                "step" => {
+                    let slots = parse_num(line_no, arg(1)?)?;
+                    let mut num = 1;
+                    for opt in &words[2..] {
+                        match opt.strip_prefix("partitions=") {
+                            Some(v) => num = parse_num(line_no, v)?,
+                            None => return parse_err(line_no, format!("unknown option {}", opt)),
+                        }
+                    }
+                    if num == 0 {
+                        return parse_err(line_no, "step needs at least 1 partition");
+                    }
+                    commands.push((
+                        line_no,
+                        Command::Step {
+                            slots,
+                            partitions: num,
+                        },
+                    ));
+                }
+                "run" => {
+                    let slots = parse_num(line_no, arg(1)?)?;
+                    let mut active = None;
+                    let mut producer = None;
+                    for opt in &words[2..] {
+                        if let Some(v) = opt.strip_prefix("active=") {
+                            active = Some(Self::parse_active(line_no, &partitions, v)?);
+                        } else if let Some(v) = opt.strip_prefix("producer=") {
+                            producer = Some(Self::parse_producer(line_no, &partitions, v)?);
+                        } else {
+                            return parse_err(line_no, format!("unknown option {}", opt));
+                        }
+                    }
+                    let (active, producer) = match (active, producer) {
+                        (Some(a), Some(p)) => (a, p),
+                        _ => return parse_err(line_no, "run needs active= and producer="),
+                    };
+                    commands.push((
+                        line_no,
+                        Command::Run {
+                            slots,
+                            active,
+                            producer,
+                        },
+                    ));
+                }
+                "repair" => {
+                    let active = Self::parse_active(line_no, &partitions, arg(1)?)?;
+                    commands.push((line_no, Command::Repair { active }));
+                }
+                "link" | "cut" => {
+                    let from = Self::parse_nodes(line_no, &partitions, arg(1)?)?;
+                    let to = Self::parse_nodes(line_no, &partitions, arg(2)?)?;
+                    let mut loss = (words[0] == "link").then_some(0.0);
+                    for opt in &words[3..] {
+                        match (opt.strip_prefix("loss="), loss) {
+                            (Some(v), Some(_)) => loss = Some(parse_num(line_no, v)?),
+                            _ => return parse_err(line_no, format!("unknown option {}", opt)),
+                        }
+                    }
+                    if loss.is_some_and(|p| !(0.0..=1.0).contains(&p)) {
+                        return parse_err(line_no, "loss must be between 0 and 1");
+                    }
+                    commands.push((line_no, Command::Link { from, to, loss }));
+                }
+                "heal" => commands.push((line_no, Command::Heal)),
+                "graph" => {
+                    let slots = parse_num(line_no, arg(1)?)?;
+                    commands.push((line_no, Command::Graph { slots }));
+                }
+                "mark" => commands.push((line_no, Command::Mark)),
+                "expect" => {
+                    if arg(2)? != ">=" {
+                        return parse_err(
+                            line_no,
+                            "expectations are of the form `expect <what> >= <slot>`",
+                        );
+                    }
+                    let slot = parse_num(line_no, arg(3)?)?;
+                    let expectation = match arg(1)? {
+                        "root" => Expectation::RootAtLeast(slot),
+                        "progress" => Expectation::ProgressAtLeast(slot),
+                        what => return parse_err(line_no, format!("unknown expectation {}", what)),
+                    };
+                    commands.push((line_no, Command::Expect(expectation)));
+                }
+                other => return parse_err(line_no, format!("unknown directive {}", other)),
+            }
+        }
+        if let Some(nodes) = nodes {
+            if nodes == 0 {
+                return parse_err(0, "nodes must be at least 1");
+            }
+            config.stakes = Stakes::uniform(nodes);
+        }
+        if let Err(msg) = config.validate() {
+            return parse_err(0, msg);
+        }
+        let num_nodes = config.num_nodes();
+        for (line, cmd) in &commands {
+            match cmd {
+                Command::Run {
+                    producer: Producer::Node(id),
+                    ..
+                } if *id >= num_nodes => {
+                    return parse_err(*line, format!("producer {} is out of range", id));
+                }
+                Command::Step { partitions, .. } if *partitions > num_nodes => {
+                    return parse_err(*line, "more partitions than nodes");
+                }
+                Command::Link { from, to, .. } if from.1 > num_nodes || to.1 > num_nodes => {
+                    return parse_err(*line, "link nodes are out of range");
+                }
+                _ => (),
+            }
+        }
+        if let Some((name, _)) = partitions
+            .iter()
+            .find(|(_, m)| matches!(m, Members::Nodes(nodes) if nodes.end() > num_nodes))
+        {
+            return parse_err(0, format!("partition {} is out of range", name));
+        }
+        let out_of_range = |(_, e): &(ID, ID)| *e > num_nodes;
+        if producers.iter().any(|(nodes, strategy)| {
+            out_of_range(nodes) || matches!(strategy, Strategy::Censor(c) if out_of_range(c))
+        }) {
+            return parse_err(0, "strategy nodes are out of range");
+        }
+        if voters.iter().any(|(nodes, _)| out_of_range(nodes)) {
+            return parse_err(0, "voter nodes are out of range");
+        }
+        Ok(Scenario {
+            config,
+            partitions,
+            producers,
+            voters,
+            commands,
+        })
+    }
+
+    fn parse_range(line: usize, val: &str) -> Result<(ID, ID), ScenarioError> {
+        let (start, end) = match val.split_once("..") {
+            Some(r) => r,
+            None => return parse_err(line, format!("expected a range start..end, got {}", val)),
+        };
+        let range = (parse_num(line, start)?, parse_num(line, end)?);
+        if range.0 >= range.1 {
+            return parse_err(line, format!("empty range {}", val));
+        }
+        Ok(range)
+    }
+
+    //`primary`, `rest`, `stake=F`, or a comma separated list of nodes and ranges
+    fn parse_members(line: usize, val: &str) -> Result<Members, ScenarioError> {
+        match val {
+            "primary" => return Ok(Members::Primary),
+            "rest" => return Ok(Members::Rest),
+            _ => (),
+        }
+        if let Some(v) = val.strip_prefix("stake=") {
+            let fraction: f64 = parse_num(line, v)?;
+            if !(fraction > 0.0 && fraction <= 1.0) {
+                return parse_err(line, format!("invalid stake fraction {}", v));
+            }
+            return Ok(Members::Stake(fraction));
+        }
+        if !val.contains(',') && val.contains("..") {
+            return Ok(Members::Nodes(Self::parse_range(line, val)?.into()));
+        }
+        let mut ids = vec![];
+        for item in val.split(',') {
+            if item.contains("..") {
+                let (start, end) = Self::parse_range(line, item)?;
+                ids.extend(start..end);
+            } else {
+                ids.push(parse_num(line, item)?);
+            }
+        }
+        Ok(Members::Nodes(NodeSet::ids(ids)))
+    }
+
+    //a range partition name or a range
+    fn parse_nodes(
+        line: usize,
+        partitions: &[(String, Members)],
+        val: &str,
+    ) -> Result<(ID, ID), ScenarioError> {
+        match partitions.iter().find(|(n, _)| n == val) {
+            Some((_, Members::Nodes(NodeSet::Range(start, end)))) => Ok((*start, *end)),
+            Some(_) => parse_err(line, format!("partition {} is not a node range", val)),
+            None => Self::parse_range(line, val),
+        }
+    }
+
+    fn parse_active(
+        line: usize,
+        partitions: &[(String, Members)],
+        val: &str,
+    ) -> Result<Vec<bool>, ScenarioError> {
+        let mut active = vec![false; partitions.len()];
+        for name in val.split(',') {
+            match partitions.iter().position(|(n, _)| n == name) {
+                Some(ix) => active[ix] = true,
+                None => return parse_err(line, format!("unknown partition {}", name)),
+            }
+        }
+        Ok(active)
+    }
+
+    //a partition name is its first node
+    fn parse_producer(
+        line: usize,
+        partitions: &[(String, Members)],
+        val: &str,
+    ) -> Result<Producer, ScenarioError> {
+        match partitions.iter().position(|(n, _)| n == val) {
+            Some(ix) => Ok(Producer::First(ix)),
+            None => Ok(Producer::Node(parse_num(line, val)?)),
+        }
+    }
+
+    /// the members of every partition in `network` now
+    pub fn resolve(&self, network: &Network) -> Vec<NodeSet> {
+        let config = network.config();
+        let mut sets: Vec<_> = self
+            .partitions
+            .iter()
+            .enumerate()
+            .map(|(ix, (_, members))| match members {
+                Members::Nodes(nodes) => nodes.clone(),
+                Members::Primary => NodeSet::primary(&network.forks),
+                Members::Stake(fraction) => {
+                    NodeSet::stake(config.seed, &config.stakes, *fraction, ix as u64)
+                }
+                Members::Rest => NodeSet::ids([]),
+            })
+            .collect();
+        let rest = NodeSet::rest(&sets, config.num_nodes());
+        for (ix, (_, members)) in self.partitions.iter().enumerate() {
+            if *members == Members::Rest {
+                sets[ix] = rest.clone();
+            }
+        }
+        sets
+    }
+
+    pub fn network(&self) -> Network {
+        let mut network = Network::new(self.config.clone());
+        for ((start, end), strategy) in &self.producers {
+            for id in *start..*end {
+                let strategy: Box<dyn ProducerStrategy> = match strategy {
+                    Strategy::Equivocate => Box::new(Equivocate),
+                    Strategy::MinorityFork => Box::new(MinorityFork),
+                    Strategy::Censor((s, e)) => Box::new(Censor {
+                        censored: (*s..*e).collect(),
+                    }),
+                };
+                network.set_producer(id, strategy);
+            }
+        }
+        for ((start, end), kind) in &self.voters {
+            for id in *start..*end {
+                network.set_voter(id, kind.strategy());
+            }
+        }
+        network
+    }
+
+    /// drive the network through the scenario, stops at the first failed expectation
+    /// or safety violation
+    pub fn run(&self, network: &mut Network) -> Result<(), ScenarioError> {
+        let mut mark = network.lowest_root().slot;
+        let mut graph = Graph::default();
+        for (line, cmd) in &self.commands {
+            let safety = |violation| ScenarioError::Safety {
+                line: *line,
+                violation,
+            };
+            match cmd {
+                Command::Step { slots, partitions } => {
+                    for _ in 0..*slots {
+                        network.step(*partitions).map_err(safety)?;
+                    }
+                }
+                Command::Run {
+                    slots,
+                    active,
+                    producer,
+                } => {
+                    let sets = self.resolve(network);
+                    let producer = match *producer {
+                        Producer::Node(id) => id,
+                        Producer::First(ix) => match sets[ix].first() {
+                            Some(id) => id,
+                            None => {
+                                return Err(ScenarioError::NoProducer {
+                                    line: *line,
+                                    partition: self.partitions[ix].0.clone(),
+                                })
+                            }
+                        },
+                    };
+                    for _ in 0..*slots {
+                        network
+                            .partition_step(&sets, active, producer)
+                            .map_err(safety)?;
+                    }
+                }
+                Command::Repair { active } => {
+                    let sets = self.resolve(network);
+                    network.repair_partitions(&sets, active);
+                }
+                Command::Link { from, to, loss } => match loss {
+                    Some(loss) => graph.connect(*from, *to, *loss),
+                    None => graph.cut(*from, *to),
+                },
+                Command::Heal => graph = Graph::default(),
+                Command::Graph { slots } => {
+                    for _ in 0..*slots {
+                        network.graph_step(&graph).map_err(safety)?;
+                    }
+                }
+                Command::Mark => mark = network.lowest_root().slot,
+                Command::Expect(expectation) => {
+                    let root = network.lowest_root().slot;
+                    let (ok, actual) = match expectation {
+                        Expectation::RootAtLeast(slot) => (root >= *slot, root),
+                        Expectation::ProgressAtLeast(slots) => (root >= mark + *slots, root - mark),
+                    };
+                    if !ok {
+                        return Err(ScenarioError::Failed {
+                            line: *line,
+                            expectation: *expectation,
+                            actual,
+                        });
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_split_and_heal() {
+    let text = "
+        nodes 16
+        depth 8
+        threshold 4
+        subcommittee_size 16
+        partition a 0..11
+        partition b 11..16
+        step 16
+        expect root >= 1
+        mark
+        run 32 active=b producer=b
+        repair a,b
+        step 64
+        expect progress >= 16
+    ";
+    let scenario = Scenario::parse(text, SimConfig::default()).unwrap();
+    assert_eq!(scenario.config.num_nodes(), 16);
+    let mut network = scenario.network();
+    scenario.run(&mut network).unwrap();
+
+    let failing = format!("{}\nexpect root >= 100000", text);
+    let scenario = Scenario::parse(&failing, SimConfig::default()).unwrap();
+    let mut network = scenario.network();
+    match scenario.run(&mut network) {
+        Err(ScenarioError::Failed { line, .. }) => assert_eq!(line, 16),
+        _ => panic!("expected the scenario to fail"),
+    }
+}
+
+#[test]
+fn test_partition_members() {
+    let text = "
+        nodes 24
+        depth 8
+        threshold 4
+        subcommittee_size 12
+        partition committee primary
+        partition others rest
+        partition third stake=0.33
+        partition odd 1,3,5..8
+        step 16
+        mark
+        run 24 active=committee producer=committee
+        expect progress >= 1
+        repair committee,others
+        step 48
+        expect progress >= 16
+    ";
+    let scenario = Scenario::parse(text, SimConfig::default()).unwrap();
+    let mut network = scenario.network();
+    scenario.run(&mut network).unwrap();
+    let sets = scenario.resolve(&network);
+    let primary = network.forks.latest_primary();
+    assert!((0..24).all(|id| sets[0].contains(id) == primary.contains(&id)));
+    let elsewhere = |id| [0, 2, 3].iter().any(|ix| sets[*ix].contains(id));
+    assert!((0..24).all(|id| sets[1].contains(id) != elsewhere(id)));
+    let third = match &sets[2] {
+        NodeSet::Ids(ids) => ids.len(),
+        _ => panic!("expected a node set"),
+    };
+    assert_eq!(third, 8);
+    assert_eq!(sets[3], NodeSet::ids([1, 3, 5, 6, 7]));
+}
+
+#[test]
+fn test_lossy_graph_and_eclipse() {
+    use std::collections::HashSet;
+    let text = "
+        nodes 16
+        depth 8
+        threshold 4
+        subcommittee_size 16
+        link 0..16 0..16 loss=0.2
+        graph 96
+        expect root >= 16
+        cut 0..15 15..16
+        mark
+        graph 64
+        expect progress >= 8
+    ";
+    let scenario = Scenario::parse(text, SimConfig::default()).unwrap();
+    let mut network = scenario.network();
+    scenario.run(&mut network).unwrap();
+    //the eclipsed node only sees its own blocks
+    let leaders: HashSet<Slot> = (97..=160).filter(|s| network.leader(*s) == 15).collect();
+    let seen = network.node(15).blocks().keys().filter(|s| **s > 96);
+    assert!(seen.clone().all(|s| leaders.contains(s)));
+
+    let healed = format!("{}\nheal\nmark\ngraph 64\nexpect progress >= 16", text);
+    let scenario = Scenario::parse(&healed, SimConfig::default()).unwrap();
+    let mut network = scenario.network();
+    scenario.run(&mut network).unwrap();
+    assert!(network.node(15).blocks().keys().any(|s| *s > 160));
+}
+
+#[test]
+fn test_parse_errors() {
+    let config = SimConfig::new(4);
+    assert!(Scenario::parse("partition a 0..8", config.clone()).is_err());
+    assert!(Scenario::parse("run 1 active=a producer=0", config.clone()).is_err());
+    assert!(Scenario::parse("partition a 0..2\nrun 1 active=a", config.clone()).is_err());
+    assert!(Scenario::parse("strategy bogus 0..2", config.clone()).is_err());
+    assert!(Scenario::parse("strategy censor 0..2", config.clone()).is_err());
+    assert!(Scenario::parse("strategy equivocate 0..8", config.clone()).is_err());
+    assert!(Scenario::parse("strategy censor 0..2 censor=2..4", config.clone()).is_ok());
+    assert!(Scenario::parse("voter bogus 0..2", config.clone()).is_err());
+    assert!(Scenario::parse("latency 2..=1", config.clone()).is_err());
+    assert!(Scenario::parse("latency 2 0..2", config.clone()).is_err());
+    assert!(Scenario::parse("latency 0..=2\nlatency 4 0..2 2..4", config.clone()).is_ok());
+    assert!(Scenario::parse("voter crash_silent 0..8", config.clone()).is_err());
+    assert!(Scenario::parse("voter lockout_violating 0..2", config.clone()).is_ok());
+    assert!(Scenario::parse("link 0..2 2..8", config.clone()).is_err());
+    assert!(Scenario::parse("link 0..2 2..4 loss=2", config.clone()).is_err());
+    assert!(Scenario::parse("cut 0..2 2..4 loss=0.5", config.clone()).is_err());
+    assert!(Scenario::parse("link 0..2 2..4 loss=0.5\ngraph 4\nheal", config.clone()).is_ok());
+    assert!(Scenario::parse("votes cap=x", config.clone()).is_err());
+    assert!(Scenario::parse("votes loss=0.1 cap=8 recent max_age=4 once", config.clone()).is_ok());
+    assert!(Scenario::parse("partition a stake=0", config.clone()).is_err());
+    assert!(Scenario::parse("partition a 1,9", config.clone()).is_err());
+    assert!(Scenario::parse("partition a primary\nlatency 2 a a", config.clone()).is_err());
+    assert!(Scenario::parse("partition a 0,2\nrun 1 active=a producer=a", config.clone()).is_ok());
+    for invalid in [
+        "subcommittee_epoch 0",
+        "subcommittee_size 0",
+        "depth 64",
+        "depth 8\nthreshold 9",
+        "leader_window 0",
+    ] {
+        assert!(
+            Scenario::parse(invalid, config.clone()).is_err(),
+            "{}",
+            invalid
+        );
+    }
+    assert!(Scenario::parse("bogus", config).is_err());
+}
@@ -0,0 +1,62 @@
+//! Every random choice in a run is derived from the master seed in `SimConfig`,
+//! so a failing run can be reproduced exactly from its seed.
+//!
+//! The hash and the rng are fixed algorithms. `DefaultHasher` and `StdRng` are
+//! allowed to change between Rust and rand releases, so they aren't used here.
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+
+/// independent streams of randomness drawn from the same seed
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Stream {
+    Leader,
+    Subcommittee,
+    Scenario,
+    Availability,
+    Voters,
+    Latency,
+    Loss,
+    Votes,
+    Partitions,
+    Fuzz,
+}
+
+/// splitmix64 finalizer
+pub fn hash(val: u64) -> u64 {
+    let mut z = val.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// the value of `stream` at `index`, for example the leader of a slot
+pub fn derive(seed: u64, stream: Stream, index: u64) -> u64 {
+    hash(hash(hash(seed) ^ stream as u64) ^ index)
+}
+
+/// whether a `derive`d value falls under probability `p`
+pub fn chance(val: u64, p: f64) -> bool {
+    //53 random bits as a float in [0, 1)
+    ((val >> 11) as f64) / ((1u64 << 53) as f64) < p
+}
+
+/// an rng for `stream` at `index`, for example the sampler of a subcommittee epoch
+pub fn rng(seed: u64, stream: Stream, index: u64) -> ChaCha8Rng {
+    ChaCha8Rng::seed_from_u64(derive(seed, stream, index))
+}
+
+#[test]
+fn test_stable_hash() {
+    use rand::Rng;
+    //known splitmix64 outputs, these must never change
+    assert_eq!(hash(0), 0xe220_a839_7b1d_cdaf);
+    assert_eq!(hash(1), 0x910a_2dec_8902_5cc1);
+    assert_ne!(
+        derive(0, Stream::Leader, 1),
+        derive(0, Stream::Subcommittee, 1)
+    );
+    assert_ne!(derive(0, Stream::Leader, 1), derive(1, Stream::Leader, 1));
+    let a: u64 = rng(7, Stream::Scenario, 3).gen();
+    let b: u64 = rng(7, Stream::Scenario, 3).gen();
+    assert_eq!(a, b);
+}
@@ -0,0 +1,292 @@
+//! Detects provable voting misbehavior from the votes that land in blocks.
+//!
+//! The detector replays every node's votes into a tower of its own, in the
+//! order the blocks are applied across all forks. A node that votes on a slot
+//! that doesn't descend from one of its votes that is still locked out, or that
+//! votes on two versions of the same slot, is caught with the conflicting votes
+//! as evidence. Honest votes can land after newer ones of the same node, a vote
+//! older than the latest replayed one is only checked against the versions.
+//! A producer that builds more than one version of its slot is caught with the
+//! ids of two of them.
+use crate::bank::ID;
+use crate::config::SimConfig;
+use crate::snapshot::{corrupt, Reader, SnapshotError, Writer};
+use crate::tower::{Slot, Tower, Vote};
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Evidence {
+    //`vote` is not a descendant of `locked`, which it doesn't expire
+    LockoutViolation {
+        slot: Slot,
+        node: ID,
+        locked: Vote,
+        vote: Slot,
+    },
+    //`node` voted on the versions `first_id` and `id` of slot `vote`
+    DoubleVote {
+        slot: Slot,
+        node: ID,
+        vote: Slot,
+        first_id: u64,
+        id: u64,
+    },
+    //`producer` built the versions `first_id` and `id` of block `slot`
+    Equivocation {
+        slot: Slot,
+        producer: ID,
+        first_id: u64,
+        id: u64,
+    },
+}
+
+impl Evidence {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Evidence::LockoutViolation { .. } => "lockout_violation",
+            Evidence::DoubleVote { .. } => "double_vote",
+            Evidence::Equivocation { .. } => "equivocation",
+        }
+    }
+
+    /// the block the evidence landed in
+    pub fn slot(&self) -> Slot {
+        match self {
+            Evidence::LockoutViolation { slot, .. }
+            | Evidence::DoubleVote { slot, .. }
+            | Evidence::Equivocation { slot, .. } => *slot,
+        }
+    }
+
+    pub fn node(&self) -> ID {
+        match self {
+            Evidence::LockoutViolation { node, .. } | Evidence::DoubleVote { node, .. } => *node,
+            Evidence::Equivocation { producer, .. } => *producer,
+        }
+    }
+}
+
+pub struct Detector {
+    //the votes of each node that landed so far, replayed with the node's lockouts
+    towers: Vec<Tower>,
+    //every vote slot of each node above the lowest root and the version it voted on
+    seen: Vec<HashMap<Slot, u64>>,
+    //the lowest root at the last gc, older votes were forgotten
+    root: Slot,
+    //nodes with evidence against them
+    pub offenders: HashSet<ID>,
+    //evidence since the last drain
+    pub evidence: Vec<Evidence>,
+}
+
+impl Detector {
+    pub fn new(config: &SimConfig) -> Self {
+        let tower = Tower::new(config.depth);
+        //the initial root is in every tower without being voted on
+        let seen = HashMap::from([(tower.root.slot, 0)]);
+        Detector {
+            towers: vec![tower; config.num_nodes()],
+            seen: vec![seen; config.num_nodes()],
+            root: 0,
+            offenders: HashSet::new(),
+            evidence: vec![],
+        }
+    }
+
+    /// `vote` of `node` on the version `id` of its slot landed in block `slot`,
+    /// `in_fork` tells the slots of its fork down to `min`
+    pub fn observe(
+        &mut self,
+        slot: Slot,
+        node: ID,
+        vote: Slot,
+        id: u64,
+        in_fork: impl Fn(Slot) -> bool,
+        min: Slot,
+    ) {
+        if vote < self.root {
+            return;
+        }
+        match self.seen[node].get(&vote) {
+            Some(first_id) if *first_id == id => return,
+            Some(first_id) => {
+                self.offenders.insert(node);
+                self.evidence.push(Evidence::DoubleVote {
+                    slot,
+                    node,
+                    vote,
+                    first_id: *first_id,
+                    id,
+                });
+                return;
+            }
+            None => self.seen[node].insert(vote, id),
+        };
+        let tower = &mut self.towers[node];
+        let latest = tower.latest_vote().unwrap_or(&tower.root).slot;
+        if vote <= latest {
+            //landed after a newer vote, its lockouts were already replayed past
+            return;
+        }
+        //votes older than `min` are either rooted or on a pruned fork, so they can't be checked
+        let locked = tower
+            .votes
+            .iter()
+            .find(|v| v.slot >= min && v.slot + v.lockout >= vote && !in_fork(v.slot));
+        if let Some(locked) = locked {
+            self.offenders.insert(node);
+            self.evidence.push(Evidence::LockoutViolation {
+                slot,
+                node,
+                locked: *locked,
+                vote,
+            });
+            //keep following the node on its new fork
+            tower.votes.retain(|v| v.slot < min || in_fork(v.slot));
+        }
+        tower.apply(&Vote::new(vote)).unwrap();
+    }
+
+    /// `producer` built a second version `id` of block `slot`
+    pub fn equivocation(&mut self, slot: Slot, producer: ID, first_id: u64, id: u64) {
+        self.offenders.insert(producer);
+        self.evidence.push(Evidence::Equivocation {
+            slot,
+            producer,
+            first_id,
+            id,
+        });
+    }
+
+    /// forget the votes below the new lowest root
+    pub fn gc(&mut self, lowest_root: Slot) {
+        self.root = lowest_root;
+        for seen in &mut self.seen {
+            seen.retain(|s, _| *s >= lowest_root);
+        }
+    }
+
+    /// pending evidence is not saved
+    pub fn encode(&self, w: &mut Writer) {
+        for (tower, seen) in self.towers.iter().zip(&self.seen) {
+            w.tower(tower);
+            w.map(seen);
+        }
+        w.u64(self.root);
+        w.ids(&self.offenders);
+    }
+
+    pub fn decode(r: &mut Reader, config: &SimConfig) -> Result<Self, SnapshotError> {
+        let mut detector = Detector::new(config);
+        for id in 0..config.num_nodes() {
+            detector.towers[id] = r.tower()?;
+            detector.seen[id] = r.map()?;
+        }
+        detector.root = r.u64()?;
+        detector.offenders = r.ids()?;
+        if detector
+            .offenders
+            .iter()
+            .any(|id| *id >= config.num_nodes())
+        {
+            return corrupt("offender is out of range");
+        }
+        Ok(detector)
+    }
+}
+
+#[test]
+fn test_lockout_violation_and_double_vote() {
+    let config = SimConfig::new(2);
+    let mut detector = Detector::new(&config);
+    // 0 -> 1 -> 2
+    //   \-> 3 -> 4
+    let left = |s: Slot| [0, 1, 2].contains(&s);
+    let right = |s: Slot| [0, 3, 4].contains(&s);
+    detector.observe(2, 0, 1, 0, left, 0);
+    detector.observe(2, 0, 2, 0, left, 0);
+    //the same votes landing again are not evidence
+    detector.observe(3, 0, 1, 0, left, 0);
+    assert!(detector.evidence.is_empty());
+    //1 is locked out until slot 5
+    detector.observe(4, 0, 3, 0, right, 0);
+    assert_eq!(
+        detector.evidence,
+        vec![Evidence::LockoutViolation {
+            slot: 4,
+            node: 0,
+            locked: Vote {
+                slot: 2,
+                lockout: 2
+            },
+            vote: 3,
+        }]
+    );
+    //node 1 switches after its vote expired
+    detector.observe(2, 1, 1, 0, left, 0);
+    let far = |s: Slot| [0, 4].contains(&s);
+    detector.observe(5, 1, 4, 0, far, 0);
+    //an older vote that lands late is not evidence
+    detector.observe(6, 1, 3, 0, right, 0);
+    assert_eq!(detector.evidence.len(), 1);
+    //another version of 4 is
+    detector.observe(7, 1, 4, 1, far, 0);
+    assert_eq!(
+        detector.evidence[1],
+        Evidence::DoubleVote {
+            slot: 7,
+            node: 1,
+            vote: 4,
+            first_id: 0,
+            id: 1,
+        }
+    );
+    assert_eq!(detector.offenders, [0, 1].into_iter().collect());
+}
+
+#[test]
+fn test_lockout_violators_are_caught() {
+    use crate::network::Network;
+    use crate::voter::{self, VoterKind};
+    let mut config = SimConfig::new(40);
+    config.subcommittee_size = 16;
+    config.seed = 2;
+    let byzantine = [(VoterKind::LockoutViolating, 0.3)];
+    let violators: HashSet<ID> = voter::assign(config.seed, &config.stakes, &byzantine)
+        .into_iter()
+        .map(|(id, _)| id)
+        .collect();
+    let mut network = Network::new(config);
+    network.set_voters(&byzantine);
+    for slot in 0..512 {
+        //three way splits make the violators switch forks while locked out
+        let partitions = if slot % 128 < 40 { 3 } else { 1 };
+        network.step(partitions).unwrap();
+    }
+    let offenders = &network.forks.detector.offenders;
+    assert!(!offenders.is_empty());
+    assert!(offenders.is_subset(&violators));
+    assert!(network.metrics.slashable >= offenders.len() as u64);
+}
+
+#[test]
+fn test_reordered_honest_votes() {
+    use crate::events::{Event, MemorySink};
+    use crate::latency::{Delay, Latency};
+    use crate::network::Network;
+    //random delays reorder the towers of each node and late ones still land
+    let mut config = SimConfig::new(16);
+    config.subcommittee_size = 16;
+    config.latency = Latency::uniform(Delay::Uniform { min: 0, max: 8 });
+    config.votes.land_once = true;
+    let mut network = Network::new(config);
+    let sink = MemorySink::default();
+    network.set_sink(Box::new(sink.clone()));
+    for _ in 0..256 {
+        network.step(1).unwrap();
+    }
+    let events = sink.events();
+    assert!(!events.iter().any(|e| matches!(e, Event::Slashable { .. })));
+    assert!(network.forks.detector.offenders.is_empty());
+}
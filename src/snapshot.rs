@@ -0,0 +1,264 @@
+//! Versioned binary snapshots of the network state.
+//!
+//! A snapshot is the magic bytes, the format version and then every field of
+//! the network as LEB128 varints, in the order each type's `encode` writes them.
+//! Sets and maps are written sorted, so equal states produce equal snapshots.
+//! Bump `VERSION` whenever the layout changes, old snapshots are rejected.
+use crate::tower::{Slot, Tower, Vote};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io;
+
+pub const MAGIC: &[u8; 8] = b"TOWERSIM";
+pub const VERSION: u64 = 10;
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(io::Error),
+    //not a snapshot file
+    Magic,
+    Version { found: u64 },
+    Corrupt(String),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::Io(e) => write!(f, "{}", e),
+            SnapshotError::Magic => write!(f, "not a snapshot"),
+            SnapshotError::Version { found } => write!(
+                f,
+                "snapshot version {} is not supported, expected {}",
+                found, VERSION
+            ),
+            SnapshotError::Corrupt(msg) => write!(f, "corrupt snapshot: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+impl From<io::Error> for SnapshotError {
+    fn from(e: io::Error) -> Self {
+        SnapshotError::Io(e)
+    }
+}
+
+pub fn corrupt<T>(msg: impl Into<String>) -> Result<T, SnapshotError> {
+    Err(SnapshotError::Corrupt(msg.into()))
+}
+
+pub struct Writer {
+    pub buf: Vec<u8>,
+}
+
+impl Default for Writer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Writer {
+    /// starts with the header
+    pub fn new() -> Self {
+        let mut w = Writer {
+            buf: MAGIC.to_vec(),
+        };
+        w.u64(VERSION);
+        w
+    }
+
+    pub fn u64(&mut self, mut val: u64) {
+        while val >= 0x80 {
+            self.buf.push(val as u8 | 0x80);
+            val >>= 7;
+        }
+        self.buf.push(val as u8);
+    }
+    pub fn usize(&mut self, val: usize) {
+        self.u64(val as u64);
+    }
+    pub fn bool(&mut self, val: bool) {
+        self.u64(val as u64);
+    }
+    pub fn u64s(&mut self, vals: &[u64]) {
+        self.usize(vals.len());
+        for v in vals {
+            self.u64(*v);
+        }
+    }
+    pub fn set(&mut self, set: &HashSet<u64>) {
+        let mut vals: Vec<_> = set.iter().cloned().collect();
+        vals.sort_unstable();
+        self.u64s(&vals);
+    }
+    pub fn ids(&mut self, set: &HashSet<usize>) {
+        let mut vals: Vec<_> = set.iter().map(|v| *v as u64).collect();
+        vals.sort_unstable();
+        self.u64s(&vals);
+    }
+    pub fn map(&mut self, map: &HashMap<u64, u64>) {
+        let mut vals: Vec<_> = map.iter().collect();
+        vals.sort_unstable();
+        self.usize(vals.len());
+        for (k, v) in vals {
+            self.u64(*k);
+            self.u64(*v);
+        }
+    }
+    pub fn pairs(&mut self, pairs: &[(u64, u64)]) {
+        self.usize(pairs.len());
+        for (a, b) in pairs {
+            self.u64(*a);
+            self.u64(*b);
+        }
+    }
+    pub fn vote(&mut self, vote: &Vote) {
+        self.u64(vote.slot);
+        self.u64(vote.lockout);
+    }
+    pub fn votes(&mut self, votes: &[Vote]) {
+        self.usize(votes.len());
+        for v in votes {
+            self.vote(v);
+        }
+    }
+    pub fn tower(&mut self, tower: &Tower) {
+        self.usize(tower.depth);
+        self.vote(&tower.root);
+        self.usize(tower.votes.len());
+        for v in &tower.votes {
+            self.vote(v);
+        }
+    }
+}
+
+pub struct Reader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    /// checks the header
+    pub fn new(buf: &'a [u8]) -> Result<Self, SnapshotError> {
+        match buf.strip_prefix(MAGIC) {
+            Some(buf) => {
+                let mut r = Reader { buf };
+                let found = r.u64()?;
+                if found != VERSION {
+                    return Err(SnapshotError::Version { found });
+                }
+                Ok(r)
+            }
+            None => Err(SnapshotError::Magic),
+        }
+    }
+
+    pub fn finish(self) -> Result<(), SnapshotError> {
+        if !self.buf.is_empty() {
+            return corrupt(format!("{} trailing bytes", self.buf.len()));
+        }
+        Ok(())
+    }
+
+    pub fn u64(&mut self) -> Result<u64, SnapshotError> {
+        let mut val = 0u64;
+        for shift in (0..64).step_by(7) {
+            let (byte, rest) = match self.buf.split_first() {
+                Some(b) => b,
+                None => return corrupt("truncated"),
+            };
+            self.buf = rest;
+            val |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(val);
+            }
+        }
+        corrupt("varint overflow")
+    }
+    pub fn usize(&mut self) -> Result<usize, SnapshotError> {
+        Ok(self.u64()? as usize)
+    }
+    pub fn bool(&mut self) -> Result<bool, SnapshotError> {
+        match self.u64()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            v => corrupt(format!("invalid bool {}", v)),
+        }
+    }
+    //a length that has to fit in the rest of the buffer, so a corrupt
+    //length can't allocate more than the snapshot size
+    pub fn count(&mut self) -> Result<usize, SnapshotError> {
+        let len = self.usize()?;
+        if len > self.buf.len() {
+            return corrupt(format!("length {} is past the end", len));
+        }
+        Ok(len)
+    }
+    pub fn u64s(&mut self) -> Result<Vec<u64>, SnapshotError> {
+        (0..self.count()?).map(|_| self.u64()).collect()
+    }
+    pub fn set(&mut self) -> Result<HashSet<Slot>, SnapshotError> {
+        Ok(self.u64s()?.into_iter().collect())
+    }
+    pub fn ids(&mut self) -> Result<HashSet<usize>, SnapshotError> {
+        Ok(self.u64s()?.into_iter().map(|v| v as usize).collect())
+    }
+    pub fn map(&mut self) -> Result<HashMap<u64, u64>, SnapshotError> {
+        (0..self.count()?)
+            .map(|_| Ok((self.u64()?, self.u64()?)))
+            .collect()
+    }
+    pub fn pairs(&mut self) -> Result<Vec<(u64, u64)>, SnapshotError> {
+        (0..self.count()?)
+            .map(|_| Ok((self.u64()?, self.u64()?)))
+            .collect()
+    }
+    //lockouts are 1 << depth
+    pub fn depth(&mut self) -> Result<usize, SnapshotError> {
+        let depth = self.usize()?;
+        if depth >= 64 {
+            return corrupt(format!("invalid depth {}", depth));
+        }
+        Ok(depth)
+    }
+    pub fn vote(&mut self) -> Result<Vote, SnapshotError> {
+        Ok(Vote {
+            slot: self.u64()?,
+            lockout: self.u64()?,
+        })
+    }
+    pub fn votes(&mut self) -> Result<Vec<Vote>, SnapshotError> {
+        (0..self.count()?).map(|_| self.vote()).collect()
+    }
+    pub fn tower(&mut self) -> Result<Tower, SnapshotError> {
+        let mut tower = Tower::new(self.depth()?);
+        tower.root = self.vote()?;
+        for _ in 0..self.count()? {
+            tower.votes.push_back(self.vote()?);
+        }
+        Ok(tower)
+    }
+}
+
+#[test]
+fn test_header_and_varints() {
+    let mut w = Writer::new();
+    for v in [0, 1, 127, 128, u64::MAX] {
+        w.u64(v);
+    }
+    let mut r = Reader::new(&w.buf).unwrap();
+    for v in [0, 1, 127, 128, u64::MAX] {
+        assert_eq!(r.u64().unwrap(), v);
+    }
+    r.finish().unwrap();
+    assert!(matches!(
+        Reader::new(b"not a snapshot"),
+        Err(SnapshotError::Magic)
+    ));
+    let mut old = MAGIC.to_vec();
+    old.push(0);
+    assert!(matches!(
+        Reader::new(&old),
+        Err(SnapshotError::Version { found: 0 })
+    ));
+}
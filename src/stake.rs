@@ -0,0 +1,110 @@
+use crate::bank::ID;
+use rand::distributions::WeightedIndex;
+use rand::Rng;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+pub type Stake = u64;
+
+/// Per node stake table, indexed by node `ID`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Stakes {
+    stakes: Vec<Stake>,
+    total: Stake,
+}
+
+impl Stakes {
+    pub fn new(stakes: Vec<Stake>) -> Self {
+        assert!(!stakes.is_empty(), "empty stake table");
+        let total = stakes.iter().sum();
+        assert!(total > 0, "stake table has no stake");
+        Stakes { stakes, total }
+    }
+
+    /// every node has the same stake, this is equivalent to one node one vote
+    pub fn uniform(num_nodes: usize) -> Self {
+        Self::new(vec![1; num_nodes])
+    }
+
+    /// heavy tailed stake distribution, smaller `alpha` is more skewed
+    pub fn pareto(num_nodes: usize, alpha: f64, seed: u64) -> Self {
+        assert!(alpha > 0.0);
+        let mut rng = ChaCha8Rng::seed_from_u64(seed);
+        let stakes = (0..num_nodes)
+            .map(|_| {
+                let u: f64 = rng.gen_range(f64::EPSILON..1.0);
+                //scale so the smallest possible stake is still 1000 lamports
+                (1000.0 / u.powf(1.0 / alpha)).min(1e15) as Stake
+            })
+            .collect();
+        Self::new(stakes)
+    }
+
+    /// load a stake table with one stake per line, the line number is the node ID
+    /// empty lines and lines starting with `#` are ignored
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let text = fs::read_to_string(path)?;
+        let mut stakes = vec![];
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let stake = line.parse().map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("invalid stake {:?}: {}", line, e),
+                )
+            })?;
+            stakes.push(stake);
+        }
+        let total = stakes
+            .iter()
+            .try_fold(0, |total: Stake, s| total.checked_add(*s));
+        if matches!(total, None | Some(0)) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "stake table has no stake or too much of it",
+            ));
+        }
+        Ok(Self::new(stakes))
+    }
+
+    pub fn len(&self) -> usize {
+        self.stakes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stakes.is_empty()
+    }
+
+    pub fn get(&self, id: ID) -> Stake {
+        self.stakes[id]
+    }
+
+    pub fn total(&self) -> Stake {
+        self.total
+    }
+
+    /// total stake of a group of nodes
+    pub fn sum<'a>(&self, ids: impl IntoIterator<Item = &'a ID>) -> Stake {
+        ids.into_iter().map(|id| self.stakes[*id]).sum()
+    }
+
+    /// stake weighted node sampler
+    pub fn sampler(&self) -> WeightedIndex<Stake> {
+        WeightedIndex::new(&self.stakes).unwrap()
+    }
+}
+
+#[test]
+fn test_pareto_deterministic() {
+    let a = Stakes::pareto(100, 1.16, 7);
+    let b = Stakes::pareto(100, 1.16, 7);
+    assert_eq!(a, b);
+    assert_eq!(a.len(), 100);
+    assert_eq!(a.total(), a.sum(&(0..100).collect::<Vec<_>>()));
+}
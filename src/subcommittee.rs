@@ -1,12 +1,12 @@
-use crate::bank::{ID, NUM_NODES};
+use crate::bank::ID;
+use crate::config::SimConfig;
+use crate::seed::{self, Stream};
+use crate::snapshot::{corrupt, Reader, SnapshotError, Writer};
 use crate::tower::Slot;
-use rand::rngs::StdRng;
-use rand::Rng;
-use rand::SeedableRng;
-use std::collections::hash_map::DefaultHasher;
+use rand::distributions::Distribution;
 use std::collections::HashSet;
-use std::hash::{Hash, Hasher};
 
+//defaults, see `SimConfig`
 pub const SUBCOMMITTEE_EPOCH: usize = 1;
 pub const SUBCOMMITTEE_SIZE: usize = 200;
 
@@ -22,15 +22,15 @@ pub struct Subcommittee {
     pub parent_super_root: Slot,
 }
 
-#[derive(PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Phase {
     FlipPrimary,
     SwapSecondary,
 }
 
-impl Default for Subcommittee {
-    fn default() -> Self {
-        let primary = Self::calc_subcommittee(0);
+impl Subcommittee {
+    pub fn new(config: &SimConfig) -> Self {
+        let primary = Self::calc_subcommittee(0, config);
         let secondary = primary.clone();
         Self {
             parent_super_root: 0,
@@ -41,16 +41,32 @@ impl Default for Subcommittee {
             secondary,
         }
     }
-}
 
-pub fn hash(val: u64) -> u64 {
-    let mut h = DefaultHasher::new();
-    val.hash(&mut h);
-    h.finish()
-}
+    pub fn encode(&self, w: &mut Writer) {
+        w.ids(&self.primary);
+        w.ids(&self.secondary);
+        w.usize(self.num_super_roots);
+        w.usize(self.parent_num_super_roots);
+        w.u64(self.super_root);
+        w.u64(self.parent_super_root);
+    }
 
-impl Subcommittee {
-    pub fn child(self: &Self) -> Self {
+    pub fn decode(r: &mut Reader, num_nodes: usize) -> Result<Self, SnapshotError> {
+        let (primary, secondary) = (r.ids()?, r.ids()?);
+        if primary.iter().chain(&secondary).any(|id| *id >= num_nodes) {
+            return corrupt("subcommittee member is out of range");
+        }
+        Ok(Self {
+            primary,
+            secondary,
+            num_super_roots: r.usize()?,
+            parent_num_super_roots: r.usize()?,
+            super_root: r.u64()?,
+            parent_super_root: r.u64()?,
+        })
+    }
+
+    pub fn child(&self) -> Self {
         Self {
             parent_super_root: self.super_root,
             super_root: self.super_root,
@@ -62,55 +78,75 @@ impl Subcommittee {
             secondary: self.secondary.clone(),
         }
     }
-    pub fn init_child(&mut self, parent: &Self) {
-        if self.epoch() != parent.epoch() {
-            let epoch = self.epoch();
-            match self.phase() {
+    pub fn init_child(&mut self, parent: &Self, config: &SimConfig) {
+        if let Some(phase) = self.rotation(parent, config) {
+            let epoch = self.epoch(config);
+            match phase {
                 Phase::FlipPrimary => {
                     std::mem::swap(&mut self.primary, &mut self.secondary);
-                    println!("FLIP PRIMARY {:?}", self.primary);
                 }
                 Phase::SwapSecondary => {
-                    self.secondary = Self::calc_subcommittee(epoch);
-                    println!("SWAP SECONDARY {:?}", self.secondary);
+                    self.secondary = Self::calc_subcommittee(epoch, config);
                 }
             }
         }
     }
 
-    pub fn freeze(&mut self, primary: Slot, secondary: Slot, oc_slot: bool) {
-        if self.super_root > primary {
-            println!("SR {} ahead of primary {}", self.super_root, primary);
+    //the rotation that is activated in this child, if it starts a new epoch
+    pub fn rotation(&self, parent: &Self, config: &SimConfig) -> Option<Phase> {
+        if self.epoch(config) != parent.epoch(config) {
+            Some(self.phase(config))
+        } else {
+            None
         }
+    }
+
+    pub fn freeze(&mut self, primary: Slot, secondary: Slot, oc_slot: bool) {
         let super_root = core::cmp::min(primary, secondary);
         //activate the super root
         if self.super_root < super_root && oc_slot {
             self.super_root = super_root;
             if self.super_root != self.parent_super_root {
-                self.num_super_roots = self.num_super_roots + 1;
-                println!("NEW SR: {}", self.super_root);
+                self.num_super_roots += 1;
             }
         }
     }
 
-    fn calc_subcommittee(epoch: usize) -> HashSet<ID> {
+    //stake weighted sample, heavier nodes are more likely to be picked
+    fn calc_subcommittee(epoch: usize, config: &SimConfig) -> HashSet<ID> {
         let mut set = HashSet::new();
-        let mut rng = StdRng::seed_from_u64(epoch as u64);
-        for _ in 0..SUBCOMMITTEE_SIZE {
-            set.insert(rng.gen_range(0..NUM_NODES));
+        let mut rng = seed::rng(config.seed, Stream::Subcommittee, epoch as u64);
+        let sampler = config.stakes.sampler();
+        for _ in 0..config.subcommittee_size {
+            set.insert(sampler.sample(&mut rng));
         }
         set
     }
 
-    fn epoch(&self) -> usize {
-        self.parent_num_super_roots / SUBCOMMITTEE_EPOCH
+    fn epoch(&self, config: &SimConfig) -> usize {
+        self.parent_num_super_roots / config.subcommittee_epoch
     }
 
-    pub fn phase(&self) -> Phase {
-        match self.epoch() % 2 {
+    pub fn phase(&self, config: &SimConfig) -> Phase {
+        match self.epoch(config) % 2 {
             0 => Phase::FlipPrimary,
             1 => Phase::SwapSecondary,
             _ => panic!("invalid subcommittee phase"),
         }
     }
 }
+
+#[test]
+fn test_decode_members_out_of_range() {
+    let subcom = Subcommittee::new(&SimConfig::new(8));
+    let mut w = Writer::new();
+    subcom.encode(&mut w);
+    let max = *subcom
+        .primary
+        .iter()
+        .chain(&subcom.secondary)
+        .max()
+        .unwrap();
+    assert!(Subcommittee::decode(&mut Reader::new(&w.buf).unwrap(), max + 1).is_ok());
+    assert!(Subcommittee::decode(&mut Reader::new(&w.buf).unwrap(), max).is_err());
+}
@@ -1,6 +1,9 @@
 use std::collections::HashMap;
 use std::collections::VecDeque;
+use std::ops::{Index, IndexMut};
+use std::sync::Arc;
 
+//default tower depth, see `SimConfig::depth`
 pub const DEPTH: usize = 16;
 
 pub type Slot = u64;
@@ -15,44 +18,49 @@ impl Vote {
     pub fn new(slot: Slot) -> Self {
         Vote { slot, lockout: 2 }
     }
-    pub fn zero() -> Self {
+    pub fn zero(depth: usize) -> Self {
         Vote {
             slot: 0,
-            lockout: 1 << DEPTH,
+            lockout: 1 << depth,
         }
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TowerError {
+    //the vote is not newer than the latest vote or the root
+    VoteTooOld { latest: Slot },
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct Tower {
     pub votes: VecDeque<Vote>,
     pub root: Vote,
+    pub depth: usize,
 }
 
-impl Default for Tower {
-    fn default() -> Self {
+impl Tower {
+    pub fn new(depth: usize) -> Self {
         Tower {
-            votes: VecDeque::with_capacity(DEPTH),
-            root: Vote {
-                slot: 0,
-                lockout: 1 << DEPTH,
-            },
+            votes: VecDeque::with_capacity(depth),
+            root: Vote::zero(depth),
+            depth,
         }
     }
-}
 
-impl Tower {
-    pub fn apply(&mut self, vote: &Vote) -> Result<(), ()> {
+    pub fn apply(&mut self, vote: &Vote) -> Result<(), TowerError> {
         assert_eq!(vote.lockout, 2);
         //pop all the expired votes
         let mut expired = None;
         if self.root.slot >= vote.slot {
-            return Err(());
+            return Err(TowerError::VoteTooOld {
+                latest: self.root.slot,
+            });
         }
         for (i, v) in self.votes.iter().enumerate() {
             //apply only new votes
             if v.slot >= vote.slot {
-                return Err(());
+                return Err(TowerError::VoteTooOld { latest: v.slot });
             }
             if v.slot + v.lockout >= vote.slot {
                 break;
@@ -66,19 +74,19 @@ impl Tower {
                 self.votes.pop_front();
             }
         }
-        self.votes.push_front(vote.clone());
-        for i in 1..DEPTH {
+        self.votes.push_front(*vote);
+        for i in 1..self.depth {
             if i >= self.votes.len() {
                 break;
             }
             //double this lockout if the previous one is equal to this one
             if self.votes[i].lockout == self.votes[i - 1].lockout {
-                self.votes[i].lockout = self.votes[i].lockout * 2;
+                self.votes[i].lockout *= 2;
             }
         }
         let mut root = false;
         if let Some(oldest) = self.votes.back() {
-            if oldest.lockout == 1 << DEPTH {
+            if oldest.lockout == 1 << self.depth {
                 self.root = *oldest;
                 root = true;
             }
@@ -96,14 +104,16 @@ impl Tower {
         for e in &self.votes {
             set.insert(e.slot, e.lockout);
         }
-        if *set.get(&tower.root.slot).unwrap() < tower.root.lockout {
+        //self is the copy that landed in a bank, when votes are delayed or lost it lags
+        //the proposed tower and its slots are missing here, they have no lockout yet
+        if *set.get(&tower.root.slot).unwrap_or(&0) < tower.root.lockout {
             rv.insert(tower.root.slot, tower.root.lockout);
         }
         for e in &tower.votes {
             if e.lockout < skip_lockout {
                 continue;
             }
-            let lockout = *set.get(&e.slot).unwrap_or(&u64::MAX);
+            let lockout = *set.get(&e.slot).unwrap_or(&0);
             assert!(lockout <= e.lockout, "proposed lockout somehow decreased");
             if lockout < e.lockout {
                 rv.insert(e.slot, e.lockout);
@@ -121,11 +131,86 @@ impl Tower {
     pub fn latest_vote(&self) -> Option<&Vote> {
         self.votes.front()
     }
+
+    /// whether `apply` would accept `vote`, it has to be newer than every vote in the tower
+    pub fn accepts(&self, vote: &Vote) -> bool {
+        vote.slot > self.latest_vote().unwrap_or(&self.root).slot
+    }
+}
+
+//number of towers that are copied together when one of them changes
+const CHUNK: usize = 64;
+
+/// The towers of all the nodes in a bank. A child bank shares the chunks of its
+/// parent and only copies a chunk when a tower in it changes, so a fork costs
+/// memory for the towers that its blocks changed and not for every node.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Towers {
+    chunks: Vec<Arc<Vec<Tower>>>,
+    len: usize,
+}
+
+impl Towers {
+    pub fn new(len: usize, depth: usize) -> Self {
+        Self::from_iter((0..len).map(|_| Tower::new(depth)))
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Tower> {
+        self.chunks.iter().flat_map(|c| c.iter())
+    }
+
+    /// number of chunks that are shared with `other`
+    pub fn shared(&self, other: &Towers) -> usize {
+        self.chunks
+            .iter()
+            .zip(&other.chunks)
+            .filter(|(a, b)| Arc::ptr_eq(a, b))
+            .count()
+    }
+}
+
+impl FromIterator<Tower> for Towers {
+    fn from_iter<I: IntoIterator<Item = Tower>>(iter: I) -> Self {
+        let mut chunks: Vec<Arc<Vec<Tower>>> = vec![];
+        let mut len = 0;
+        for t in iter {
+            if len % CHUNK == 0 {
+                chunks.push(Arc::new(Vec::with_capacity(CHUNK)));
+            }
+            Arc::get_mut(chunks.last_mut().unwrap()).unwrap().push(t);
+            len += 1;
+        }
+        Towers { chunks, len }
+    }
+}
+
+impl Index<usize> for Towers {
+    type Output = Tower;
+    fn index(&self, id: usize) -> &Tower {
+        assert!(id < self.len, "tower {} is out of range", id);
+        &self.chunks[id / CHUNK][id % CHUNK]
+    }
+}
+
+impl IndexMut<usize> for Towers {
+    //copies the chunk of `id` if it is still shared with another bank
+    fn index_mut(&mut self, id: usize) -> &mut Tower {
+        assert!(id < self.len, "tower {} is out of range", id);
+        &mut Arc::make_mut(&mut self.chunks[id / CHUNK])[id % CHUNK]
+    }
 }
 
 #[test]
 fn test_apply() {
-    let mut t = Tower::default();
+    let mut t = Tower::new(DEPTH);
     let v = Vote {
         slot: 1,
         lockout: 2,
@@ -136,7 +221,7 @@ fn test_apply() {
 
 #[test]
 fn test_root() {
-    let mut t = Tower::default();
+    let mut t = Tower::new(DEPTH);
     for i in 1..(DEPTH + 1) {
         let v = Vote {
             slot: i as u64,
@@ -152,8 +237,9 @@ fn test_root() {
 }
 
 #[test]
+#[allow(clippy::useless_conversion, clippy::assign_op_pattern)]
 fn test_pop_votes() {
-    let mut t = Tower::default();
+    let mut t = Tower::new(DEPTH);
     for i in 1..DEPTH {
         let v = Vote {
             slot: i as u64,
@@ -217,3 +303,44 @@ fn test_pop_votes() {
     };
     assert_eq!(t.root, root);
 }
+
+#[test]
+fn test_increased_lockouts_of_a_lagging_tower() {
+    let mut landed = Tower::new(DEPTH);
+    let mut proposed = Tower::new(DEPTH);
+    for slot in 1..=2 {
+        landed.apply(&Vote::new(slot)).unwrap();
+    }
+    for slot in 1..=8 {
+        proposed.apply(&Vote::new(slot)).unwrap();
+    }
+    //3 to 5 never landed and are past the skipped lockouts in one step
+    let mut increased: Vec<_> = landed
+        .get_incrased_lockouts(16, &proposed)
+        .into_iter()
+        .collect();
+    increased.sort_unstable();
+    assert_eq!(
+        increased,
+        vec![(1, 256), (2, 128), (3, 64), (4, 32), (5, 16)]
+    );
+    assert!(proposed.get_incrased_lockouts(16, &proposed).is_empty());
+}
+
+#[test]
+fn test_towers_copy_on_write() {
+    let mut parent = Towers::new(CHUNK * 3 + 5, DEPTH);
+    assert_eq!(parent.len(), CHUNK * 3 + 5);
+    parent[CHUNK * 3 + 4].apply(&Vote::new(1)).unwrap();
+    let mut child = parent.clone();
+    assert_eq!(child.shared(&parent), 4);
+    child[CHUNK + 1].apply(&Vote::new(2)).unwrap();
+    //only the chunk with the changed tower is copied
+    assert_eq!(child.shared(&parent), 3);
+    assert!(parent[CHUNK + 1].votes.is_empty());
+    assert_eq!(child[CHUNK + 1].latest_vote(), Some(&Vote::new(2)));
+    assert_eq!(child[CHUNK * 3 + 4].latest_vote(), Some(&Vote::new(1)));
+    assert!(!child[CHUNK * 3 + 4].accepts(&Vote::new(1)));
+    assert_eq!(child.iter().filter(|t| !t.votes.is_empty()).count(), 2);
+    assert_eq!(parent.iter().count(), parent.len());
+}
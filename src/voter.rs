@@ -0,0 +1,313 @@
+use crate::bank::ID;
+use crate::forks::Forks;
+use crate::node::{Checks, Node, VoteOutcome};
+use crate::safety::SafetyViolation;
+use crate::seed::{self, Stream};
+use crate::stake::Stakes;
+use rand::seq::SliceRandom;
+
+/// How a node picks its vote each slot. Byzantine strategies skip some of the
+/// checks an honest node runs, the safety checks in `Forks::apply` should
+/// still hold while they control at most f of the stake.
+pub trait VoterStrategy: Send {
+    fn vote(&mut self, node: &mut Node, forks: &Forks) -> Result<VoteOutcome, SafetyViolation>;
+}
+
+/// runs every check
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Honest;
+
+impl VoterStrategy for Honest {
+    fn vote(&mut self, node: &mut Node, forks: &Forks) -> Result<VoteOutcome, SafetyViolation> {
+        node.vote(forks)
+    }
+}
+
+/// Never votes but keeps its stake. It still follows the heaviest fork,
+/// so as a leader its blocks build on it.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CrashSilent;
+
+impl VoterStrategy for CrashSilent {
+    fn vote(&mut self, node: &mut Node, forks: &Forks) -> Result<VoteOutcome, SafetyViolation> {
+        node.observe(forks)?;
+        Ok(VoteOutcome::Silent)
+    }
+}
+
+/// votes on the heaviest fork even while locked out on a conflicting one
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LockoutViolating;
+
+impl VoterStrategy for LockoutViolating {
+    fn vote(&mut self, node: &mut Node, forks: &Forks) -> Result<VoteOutcome, SafetyViolation> {
+        let checks = Checks {
+            lockout: false,
+            ..Checks::ALL
+        };
+        node.vote_with(forks, checks)
+    }
+}
+
+/// increases its lockouts without 2/3 of the bank voting on them
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ThresholdIgnoring;
+
+impl VoterStrategy for ThresholdIgnoring {
+    fn vote(&mut self, node: &mut Node, forks: &Forks) -> Result<VoteOutcome, SafetyViolation> {
+        let checks = Checks {
+            threshold: false,
+            ..Checks::ALL
+        };
+        node.vote_with(forks, checks)
+    }
+}
+
+/// switches forks without a valid switching proof
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SwitchingProofForging;
+
+impl VoterStrategy for SwitchingProofForging {
+    fn vote(&mut self, node: &mut Node, forks: &Forks) -> Result<VoteOutcome, SafetyViolation> {
+        let checks = Checks {
+            switching: false,
+            ..Checks::ALL
+        };
+        node.vote_with(forks, checks)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum VoterKind {
+    Honest,
+    CrashSilent,
+    LockoutViolating,
+    ThresholdIgnoring,
+    SwitchingProofForging,
+}
+
+impl VoterKind {
+    pub const ALL: [VoterKind; 5] = [
+        VoterKind::Honest,
+        VoterKind::CrashSilent,
+        VoterKind::LockoutViolating,
+        VoterKind::ThresholdIgnoring,
+        VoterKind::SwitchingProofForging,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            VoterKind::Honest => "honest",
+            VoterKind::CrashSilent => "crash_silent",
+            VoterKind::LockoutViolating => "lockout_violating",
+            VoterKind::ThresholdIgnoring => "threshold_ignoring",
+            VoterKind::SwitchingProofForging => "switching_proof_forging",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|k| k.name() == name)
+    }
+
+    pub fn strategy(&self) -> Box<dyn VoterStrategy> {
+        match self {
+            VoterKind::Honest => Box::new(Honest),
+            VoterKind::CrashSilent => Box::new(CrashSilent),
+            VoterKind::LockoutViolating => Box::new(LockoutViolating),
+            VoterKind::ThresholdIgnoring => Box::new(ThresholdIgnoring),
+            VoterKind::SwitchingProofForging => Box::new(SwitchingProofForging),
+        }
+    }
+}
+
+/// Pick the nodes that follow each strategy, `fractions` are of the total
+/// stake and the picks are disjoint. Random nodes are added to a strategy while
+/// that brings its stake closer to the fraction, so with uniform stakes it gets
+/// the rounded number of nodes. The same seed picks the same nodes.
+pub fn assign(seed: u64, stakes: &Stakes, fractions: &[(VoterKind, f64)]) -> Vec<(ID, VoterKind)> {
+    let total: f64 = fractions.iter().map(|(_, f)| f).sum();
+    assert!(
+        fractions.iter().all(|(_, f)| *f >= 0.0) && total <= 1.0,
+        "invalid voter fractions"
+    );
+    let mut ids: Vec<ID> = (0..stakes.len()).collect();
+    ids.shuffle(&mut seed::rng(seed, Stream::Voters, 0));
+    let mut assigned = vec![];
+    for (kind, fraction) in fractions {
+        let target = stakes.total() as f64 * fraction;
+        let mut stake = 0.0;
+        //nodes that would overshoot the target are left for the next strategies
+        ids.retain(|id| {
+            let s = stakes.get(*id) as f64;
+            if stake + s / 2.0 > target {
+                return true;
+            }
+            stake += s;
+            assigned.push((*id, *kind));
+            false
+        });
+    }
+    assigned
+}
+
+#[test]
+fn test_byzantine_voters_stay_safe() {
+    use crate::bank::Block;
+    use crate::config::SimConfig;
+    use crate::network::Network;
+    use crate::node::Refusal;
+    use crate::tower::{Slot, Vote};
+    use std::collections::{HashMap, HashSet};
+    use std::sync::{Arc, Mutex};
+    //counts the votes where the strategy did what an honest node in its place wouldn't
+    struct Audited {
+        kind: VoterKind,
+        strategy: Box<dyn VoterStrategy>,
+        deviations: Arc<Mutex<HashMap<VoterKind, u64>>>,
+    }
+    impl VoterStrategy for Audited {
+        fn vote(&mut self, node: &mut Node, forks: &Forks) -> Result<VoteOutcome, SafetyViolation> {
+            let honest = node.clone().vote(forks)?;
+            let outcome = self.strategy.vote(node, forks)?;
+            let skipped = match self.kind {
+                VoterKind::LockoutViolating => Some(Refusal::LockedOut),
+                VoterKind::ThresholdIgnoring => Some(Refusal::Threshold),
+                VoterKind::SwitchingProofForging => Some(Refusal::OptimisticConfirmation),
+                _ => None,
+            };
+            let deviated = match (&honest, &outcome) {
+                (VoteOutcome::Voted(_), VoteOutcome::Silent) => true,
+                (VoteOutcome::Refused(r), VoteOutcome::Voted(_)) => Some(r.kind()) == skipped,
+                _ => false,
+            };
+            if deviated {
+                *self
+                    .deviations
+                    .lock()
+                    .unwrap()
+                    .entry(self.kind)
+                    .or_default() += 1;
+            }
+            Ok(outcome)
+        }
+    }
+    //just under f = 1/3 of the stake is byzantine
+    let byzantine = [
+        (VoterKind::CrashSilent, 0.08),
+        (VoterKind::LockoutViolating, 0.08),
+        (VoterKind::ThresholdIgnoring, 0.08),
+        (VoterKind::SwitchingProofForging, 0.08),
+    ];
+    let assigned = assign(0, &Stakes::uniform(40), &byzantine);
+    assert_eq!(assigned.len(), 12);
+    assert_eq!(assigned, assign(0, &Stakes::uniform(40), &byzantine));
+    let ids: HashSet<ID> = assigned.iter().map(|(id, _)| *id).collect();
+    assert_eq!(ids.len(), 12);
+    //with skewed stakes each strategy gets its fraction of the stake, not of the nodes
+    let stakes = Stakes::pareto(40, 1.0, 3);
+    let max = (0..40).map(|id| stakes.get(id)).max().unwrap();
+    let assigned = assign(0, &stakes, &byzantine);
+    for (kind, fraction) in byzantine {
+        let stake = stakes.sum(
+            assigned
+                .iter()
+                .filter(|(_, k)| *k == kind)
+                .map(|(id, _)| id),
+        );
+        let target = stakes.total() as f64 * fraction;
+        assert!(
+            (stake as f64 - target).abs() <= max as f64 / 2.0,
+            "{:?}",
+            kind
+        );
+    }
+
+    let deviations = Arc::new(Mutex::new(HashMap::new()));
+    for seed in 0..3 {
+        let mut config = SimConfig::new(40);
+        config.subcommittee_size = 16;
+        config.seed = seed;
+        let assigned = assign(seed, &config.stakes, &byzantine);
+        let mut network = Network::new(config);
+        for (id, kind) in assigned {
+            let audited = Audited {
+                kind,
+                strategy: kind.strategy(),
+                deviations: deviations.clone(),
+            };
+            network.set_voter(id, Box::new(audited));
+        }
+        for slot in 0..256 {
+            //heal a three way split every 64 slots
+            let partitions = if slot % 64 < 16 { 3 } else { 1 };
+            if let Err(violation) = network.step(partitions) {
+                panic!("seed {} slot {}: {:?}", seed, slot, violation);
+            }
+        }
+        assert!(network.metrics.votes_cast > 0);
+    }
+    //the strategies got to skip the check they ignore, or stayed silent
+    let deviations = deviations.lock().unwrap();
+    for kind in [
+        VoterKind::CrashSilent,
+        VoterKind::LockoutViolating,
+        VoterKind::ThresholdIgnoring,
+    ] {
+        assert!(
+            deviations.get(&kind).cloned().unwrap_or(0) > 0,
+            "{:?}",
+            kind
+        );
+    }
+
+    //honest nodes hardly ever need a switching proof in these runs, so the forger
+    //gets a case where it does: it voted on 1, the rest of the primary moved on to
+    //3 which it doesn't see, and the fork 0 -> 2 -> 4 it sees has a single vote
+    let mut config = SimConfig::new(12);
+    config.subcommittee_size = 12;
+    let mut forks = Forks::new(Arc::new(config.clone()));
+    let mut primary: Vec<ID> = forks.fork_map[&(0, 0)]
+        .subcom
+        .primary
+        .iter()
+        .cloned()
+        .collect();
+    primary.sort_unstable();
+    let mut nodes: Vec<Node> = primary.iter().map(|id| Node::zero(*id, &config)).collect();
+    let block = |forks: &Forks, slot, parent, votes| Block {
+        slot,
+        parent,
+        parent_id: forks.key(parent).1,
+        producer: 0,
+        votes,
+    };
+    let vote_on = |forks: &Forks, node: &mut Node, blocks: &[Slot]| {
+        for slot in blocks {
+            node.set_active_block(forks.key(*slot));
+        }
+        assert!(matches!(node.vote(forks).unwrap(), VoteOutcome::Voted(_)));
+        (node.id, node.votes())
+    };
+    forks.apply(&block(&forks, 1, 0, vec![])).unwrap();
+    vote_on(&forks, &mut nodes[0], &[1]);
+    forks.apply(&block(&forks, 2, 0, vec![])).unwrap();
+    forks.apply(&block(&forks, 3, 1, vec![])).unwrap();
+    let votes = vec![vote_on(&forks, &mut nodes[1], &[2])];
+    forks.apply(&block(&forks, 4, 2, votes)).unwrap();
+    let votes = nodes[2..]
+        .iter_mut()
+        .map(|n| vote_on(&forks, n, &[1, 3]))
+        .collect();
+    forks.apply(&block(&forks, 5, 3, votes)).unwrap();
+    let forger = &mut nodes[0];
+    forger.set_active_block(forks.key(2));
+    forger.set_active_block(forks.key(4));
+    match forger.clone().vote(&forks).unwrap() {
+        VoteOutcome::Refused(reason) => assert_eq!(reason.kind(), Refusal::OptimisticConfirmation),
+        outcome => panic!("{:?}", outcome),
+    }
+    assert_eq!(
+        SwitchingProofForging.vote(forger, &forks).unwrap(),
+        VoteOutcome::Voted(Vote::new(4))
+    );
+}